@@ -0,0 +1,399 @@
+#[cfg(feature = "zip")]
+use std::io::{Cursor, Read, Write};
+#[cfg(feature = "tar")]
+use std::time::{Duration, SystemTime};
+#[cfg(feature = "tar")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+#[cfg(feature = "tar")]
+use tokio_stream::StreamExt;
+#[cfg(any(feature = "zip", feature = "tar"))]
+use crate::{is_safe_name, FileOrFolder, NamedFile, NamedFolder, NamedSymlink, TemplateContents};
+
+#[cfg(feature = "zip")]
+impl TemplateContents{
+    /// Packs the template into a ZIP archive in memory, e.g. to inspect a received template
+    ///
+    /// Every folder gets its own entry, so empty folders are kept. Files are deflated, their mode is stored as unix permissions.
+    /// Symlinks are stored as symlink entries. Modification times aren't kept.
+    pub fn to_zip_bytes(&self) -> std::io::Result<Vec<u8>>{
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        write_zip_entries(&mut writer, "", &self.contents)?;
+
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Reads a template from a ZIP archive in memory, the reverse of [TemplateContents::to_zip_bytes]
+    ///
+    /// Folders without an own entry (only implied by the paths of their files) are created as well.
+    /// Fails with [std::io::ErrorKind::InvalidInput] if an entry has an unsafe path, e.g. one containing "..".
+    pub fn from_zip_bytes(bytes: &[u8]) -> std::io::Result<TemplateContents>{
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+        let mut contents = Vec::new();
+
+        for i in 0..archive.len(){
+            let mut entry = archive.by_index(i)?;
+            let path = entry.name()?.into_owned();
+            let mut components = archive_path_components(&path, "zip")?;
+
+            if entry.is_dir(){
+                zip_folder(&mut contents, &components);
+                continue
+            }
+
+            let name = components.pop().unwrap_or_default().to_string();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+
+            let item = if entry.is_symlink(){
+                let target = String::from_utf8(content).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Symlink target of {} isn't valid UTF-8", path)))?;
+                FileOrFolder::Symlink(NamedSymlink{
+                    name,
+                    target,
+                })
+            }else{
+                FileOrFolder::File(NamedFile{
+                    name,
+                    content,
+                    mode: entry.unix_mode().map(|mode| mode & 0o7777),
+                    modified: None,
+                })
+            };
+            zip_folder(&mut contents, &components).push(item);
+        }
+
+        Ok(TemplateContents{
+            contents,
+        })
+    }
+}
+
+#[cfg(feature = "zip")]
+fn write_zip_entries<W: Write + std::io::Seek>(writer: &mut zip::ZipWriter<W>, prefix: &str, contents: &[FileOrFolder]) -> std::io::Result<()>{
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for item in contents{
+        match item{
+            FileOrFolder::File(file) => {
+                let options = match file.mode{
+                    Some(mode) => options.unix_permissions(mode),
+                    // Without attributes the entry is read back without a mode instead of the default 0o644
+                    None => options.external_attributes(0),
+                };
+                writer.start_file(format!("{}{}", prefix, file.name), options)?;
+                writer.write_all(&file.content)?;
+            },
+            FileOrFolder::Folder(folder) => {
+                let path = format!("{}{}/", prefix, folder.name);
+                writer.add_directory(path.as_str(), options)?;
+                write_zip_entries(writer, &path, &folder.contents)?;
+            },
+            FileOrFolder::Symlink(symlink) => {
+                writer.add_symlink(format!("{}{}", prefix, symlink.name), &symlink.target, options)?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the contents of the folder at `path`, creating missing folders on the way
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn zip_folder<'a>(contents: &'a mut Vec<FileOrFolder>, path: &[&str]) -> &'a mut Vec<FileOrFolder>{
+    let Some((name, rest)) = path.split_first() else {
+        return contents
+    };
+
+    let index = match contents.iter().position(|item| matches!(item, FileOrFolder::Folder(folder) if folder.name == *name)){
+        Some(index) => index,
+        None => {
+            contents.push(FileOrFolder::Folder(NamedFolder{
+                name: name.to_string(),
+                contents: Vec::new(),
+            }));
+            contents.len() - 1
+        }
+    };
+
+    match &mut contents[index]{
+        FileOrFolder::Folder(folder) => zip_folder(&mut folder.contents, rest),
+        _ => unreachable!("Index points to a folder"),
+    }
+}
+
+/// Splits the path of an archive entry into its folder names, rejecting paths which would leave the archive
+#[cfg(any(feature = "zip", feature = "tar"))]
+fn archive_path_components<'a>(path: &'a str, archive_type: &str) -> std::io::Result<Vec<&'a str>>{
+    let components : Vec<&str> = path.split('/').filter(|component| !component.is_empty() && *component != ".").collect();
+    if components.is_empty() || !components.iter().all(|component| is_safe_name(component)){
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Unsafe path in {} archive: {}", archive_type, path)))
+    }
+
+    Ok(components)
+}
+
+#[cfg(feature = "tar")]
+impl TemplateContents{
+    /// Writes the template as a tar archive to `writer`, e.g. to pipe it into an external process
+    ///
+    /// Entries are written one after another, so only one file is encoded at a time. Every folder gets its own entry,
+    /// so empty folders are kept. The mode of a file is stored in the header (0 if unknown), the modification time
+    /// in a pax extension with nanosecond precision.
+    pub async fn write_tar<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> std::io::Result<()>{
+        let mut entries = Vec::new();
+        collect_tar_entries("", &self.contents, &mut entries);
+
+        let mut builder = tokio_tar::Builder::new_non_terminated(writer);
+        for (path, item) in entries{
+            write_tar_entry(&mut builder, path, item).await?;
+        }
+
+        builder.finish().await
+    }
+
+    /// Reads a template from a tar archive, the reverse of [TemplateContents::write_tar]
+    ///
+    /// The archive is read entry by entry from `reader`. Folders without an own entry are created as well,
+    /// other entry types than files, folders and symlinks (e.g. devices) are skipped.
+    /// Fails with [std::io::ErrorKind::InvalidInput] if an entry has an unsafe path, e.g. one containing "..".
+    pub async fn read_tar<R: AsyncRead + Unpin>(reader: R) -> std::io::Result<TemplateContents>{
+        let mut archive = tokio_tar::Archive::new(reader);
+        let mut entries = archive.entries()?;
+        let mut contents = Vec::new();
+
+        while let Some(entry) = entries.next().await{
+            let mut entry = entry?;
+            let path = String::from_utf8(entry.path_bytes()?.into_owned())
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Path in tar archive isn't valid UTF-8"))?;
+            let entry_type = entry.header().entry_type();
+            // Archives created with e.g. `tar -C dir .` contain an entry for the root folder
+            if entry_type.is_dir() && path.split('/').all(|component| component.is_empty() || component == "."){
+                continue
+            }
+            let mut components = archive_path_components(&path, "tar")?;
+
+            if entry_type.is_dir(){
+                zip_folder(&mut contents, &components);
+                continue
+            }
+
+            let name = components.pop().unwrap_or_default().to_string();
+            let item = if entry_type.is_symlink(){
+                let target = entry.link_name_bytes()?.map(|target| target.into_owned()).unwrap_or_default();
+                let target = String::from_utf8(target).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Symlink target of {} isn't valid UTF-8", path)))?;
+                FileOrFolder::Symlink(NamedSymlink{
+                    name,
+                    target,
+                })
+            }else if entry_type.is_file(){
+                let mode = entry.header().mode()? & 0o7777;
+                let modified = tar_modified(&mut entry).await?;
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).await?;
+
+                FileOrFolder::File(NamedFile{
+                    name,
+                    content,
+                    mode: (mode != 0).then_some(mode),
+                    modified,
+                })
+            }else{
+                tracing::warn!(path, "Skipped unsupported entry in tar archive");
+                continue
+            };
+            zip_folder(&mut contents, &components).push(item);
+        }
+
+        Ok(TemplateContents{
+            contents,
+        })
+    }
+}
+
+/// Lists all entries of the tree with their path in the archive, folders before their contents
+#[cfg(feature = "tar")]
+fn collect_tar_entries<'a>(prefix: &str, contents: &'a [FileOrFolder], entries: &mut Vec<(String, &'a FileOrFolder)>){
+    for item in contents{
+        match item{
+            FileOrFolder::Folder(folder) => {
+                let path = format!("{}{}/", prefix, folder.name);
+                entries.push((path.clone(), item));
+                collect_tar_entries(&path, &folder.contents, entries);
+            },
+            _ => entries.push((format!("{}{}", prefix, item.name()), item)),
+        }
+    }
+}
+
+#[cfg(feature = "tar")]
+async fn write_tar_entry<W: AsyncWrite + Unpin + Send>(builder: &mut tokio_tar::Builder<W>, path: String, item: &FileOrFolder) -> std::io::Result<()>{
+    let mut header = tokio_tar::Header::new_ustar();
+    match item{
+        FileOrFolder::File(file) => {
+            let mtime = file.modified.and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok());
+            if let Some(mtime) = mtime{
+                let mut pax_header = tokio_tar::Header::new_ustar();
+                let record = pax_record("mtime", &format!("{}.{:09}", mtime.as_secs(), mtime.subsec_nanos()));
+                pax_header.set_entry_type(tokio_tar::EntryType::XHeader);
+                pax_header.set_size(record.len() as u64);
+                builder.append_data(&mut pax_header, "PaxHeader", record.as_bytes()).await?;
+            }
+
+            header.set_entry_type(tokio_tar::EntryType::Regular);
+            header.set_mode(file.mode.unwrap_or(0));
+            header.set_mtime(mtime.map(|mtime| mtime.as_secs()).unwrap_or(0));
+            header.set_size(file.content.len() as u64);
+            builder.append_data(&mut header, path, file.content.as_slice()).await
+        },
+        FileOrFolder::Folder(_) => {
+            header.set_entry_type(tokio_tar::EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, path, tokio::io::empty()).await
+        },
+        FileOrFolder::Symlink(symlink) => {
+            header.set_entry_type(tokio_tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            header.set_link_name(&symlink.target)?;
+            builder.append_data(&mut header, path, tokio::io::empty()).await
+        },
+    }
+}
+
+/// Formats a pax extended header record, which is prefixed with its own length in bytes
+#[cfg(feature = "tar")]
+fn pax_record(key: &str, value: &str) -> String{
+    let rest = format!(" {}={}\n", key, value);
+    let mut len = rest.len();
+    while (len.to_string().len() + rest.len()) != len{
+        len = len.to_string().len() + rest.len();
+    }
+
+    format!("{}{}", len, rest)
+}
+
+/// Modification time of a tar entry, from its pax extension if there is one, otherwise from the header (None if 0)
+#[cfg(feature = "tar")]
+async fn tar_modified<R: AsyncRead + Unpin>(entry: &mut tokio_tar::Entry<R>) -> std::io::Result<Option<SystemTime>>{
+    if let Some(extensions) = entry.pax_extensions().await?{
+        for extension in extensions{
+            let extension = extension?;
+            if extension.key() != Ok("mtime"){
+                continue
+            }
+
+            // Seconds with an optional fraction, e.g. "1600000000.123456789"
+            let value = extension.value().unwrap_or_default();
+            let (secs, fraction) = value.split_once('.').unwrap_or((value, ""));
+            let nanos : String = fraction.chars().chain(std::iter::repeat('0')).take(9).collect();
+            if let (Ok(secs), Ok(nanos)) = (secs.parse::<u64>(), nanos.parse::<u32>()){
+                return Ok(Some(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos)))
+            }
+        }
+    }
+
+    let mtime = entry.header().mtime()?;
+    Ok((mtime != 0).then(|| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)))
+}
+
+#[cfg(all(test, any(feature = "zip", feature = "tar")))]
+mod tests{
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::tests::{file, folder};
+
+    fn symlink(name: &str, target: &str) -> FileOrFolder{
+        FileOrFolder::Symlink(NamedSymlink{
+            name: name.to_string(),
+            target: target.to_string(),
+        })
+    }
+
+    fn template() -> TemplateContents{
+        let script = NamedFile{
+            name: "render.sh".to_string(),
+            content: b"#!/bin/sh\n".to_vec(),
+            mode: Some(0o755),
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789)),
+        };
+
+        TemplateContents{
+            contents: vec![
+                file("main.hbs", b"{{title}}"),
+                folder("assets", vec![
+                    file("style.css", b"body {}"),
+                    folder("fonts", vec![
+                        file("font.woff2", &[0, 1, 2, 255]),
+                    ]),
+                    symlink("logo.svg", "../logo.svg"),
+                ]),
+                FileOrFolder::File(script),
+            ],
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_round_trip(){
+        let mut template = template();
+        // Modification times aren't stored in zip archives
+        clear_modified(&mut template.contents);
+
+        let bytes = template.to_zip_bytes().unwrap();
+        assert_eq!(TemplateContents::from_zip_bytes(&bytes).unwrap(), template);
+    }
+
+    #[cfg(feature = "zip")]
+    fn clear_modified(contents: &mut [FileOrFolder]){
+        for item in contents{
+            match item{
+                FileOrFolder::File(file) => file.modified = None,
+                FileOrFolder::Folder(folder) => clear_modified(&mut folder.contents),
+                FileOrFolder::Symlink(_) => {},
+            }
+        }
+    }
+
+    #[cfg(feature = "tar")]
+    #[tokio::test]
+    async fn tar_round_trip(){
+        let template = template();
+
+        let mut bytes = Vec::new();
+        template.write_tar(&mut bytes).await.unwrap();
+        assert_eq!(TemplateContents::read_tar(bytes.as_slice()).await.unwrap(), template);
+    }
+
+    fn template_with_empty_folders() -> TemplateContents{
+        TemplateContents{
+            contents: vec![
+                folder("output", Vec::new()),
+                folder("assets", vec![
+                    folder("fonts", vec![
+                        folder("empty", Vec::new()),
+                    ]),
+                    file("style.css", b"body {}"),
+                ]),
+            ],
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn zip_keeps_empty_folders(){
+        let template = template_with_empty_folders();
+
+        let bytes = template.to_zip_bytes().unwrap();
+        assert_eq!(TemplateContents::from_zip_bytes(&bytes).unwrap(), template);
+    }
+
+    #[cfg(feature = "tar")]
+    #[tokio::test]
+    async fn tar_keeps_empty_folders(){
+        let template = template_with_empty_folders();
+
+        let mut bytes = Vec::new();
+        template.write_tar(&mut bytes).await.unwrap();
+        assert_eq!(TemplateContents::read_tar(bytes.as_slice()).await.unwrap(), template);
+    }
+}
@@ -1,7 +1,12 @@
 use std::fs::File;
 use std::io::BufReader;
-use tokio_rustls::rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer};
-use tokio_rustls::rustls::RootCertStore;
+use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use tokio_rustls::rustls::pki_types::{CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use tokio_rustls::rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, OtherError, RootCertStore, SignatureScheme};
 
 pub fn load_root_ca(path: String) -> RootCertStore {
     // Load certificates
@@ -35,4 +40,203 @@ pub fn load_crl(path: String) -> Vec<CertificateRevocationListDer<'static>>{
     let res = rustls_pemfile::crls(&mut crl_reader).map(|cert|cert.expect("Couldn't load CRL!")).collect();
 
     res
-}
\ No newline at end of file
+}
+/// SHA-256 fingerprint of a certificate as colon separated uppercase hex (e.g. "AB:CD:..."), the format openssl prints
+///
+/// Used to pin certificates with [PinnedCertVerifier], e.g. read it off a generated certificate with `fingerprint(&load_client_cert(path)[0])`.
+pub fn fingerprint(cert: &CertificateDer) -> String{
+    Sha256::digest(cert.as_ref()).iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(":")
+}
+
+/// Accepts exactly the certificates with one of the pinned SHA-256 fingerprints instead of checking a CA chain
+///
+/// Implements both [ServerCertVerifier] and [ClientCertVerifier], so it can be used on either side, e.g.
+/// `ClientConfig::builder().dangerous().with_custom_certificate_verifier(verifier)` or
+/// `ServerConfig::builder().with_client_cert_verifier(verifier)`.
+/// Host names and validity periods aren't checked, the handshake signatures are. On mismatch the handshake fails with
+/// [CertificateError::Other] wrapping [CertError::FingerprintMismatch].
+#[derive(Debug)]
+pub struct PinnedCertVerifier{
+    fingerprints: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier{
+    /// Creates a verifier accepting the given fingerprints, see [fingerprint]. Colons, spaces and the case of the hex digits are ignored.
+    /// Uses the default crypto provider if one is installed, aws-lc-rs otherwise.
+    pub fn new(fingerprints: &[&str]) -> Result<Arc<PinnedCertVerifier>, CertError>{
+        let fingerprints = fingerprints.iter().map(|fingerprint| parse_fingerprint(fingerprint)).collect::<Result<Vec<[u8; 32]>, CertError>>()?;
+        let provider = CryptoProvider::get_default().cloned().unwrap_or_else(|| Arc::new(tokio_rustls::rustls::crypto::aws_lc_rs::default_provider()));
+
+        Ok(Arc::new(PinnedCertVerifier{
+            fingerprints,
+            provider,
+        }))
+    }
+
+    fn check(&self, end_entity: &CertificateDer) -> Result<(), tokio_rustls::rustls::Error>{
+        let digest : [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+        if self.fingerprints.contains(&digest){
+            Ok(())
+        }else{
+            let error = CertError::FingerprintMismatch{
+                actual: fingerprint(end_entity),
+            };
+            Err(CertificateError::Other(OtherError(Arc::new(error))).into())
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier{
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        self.check(end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+impl ClientCertVerifier for PinnedCertVerifier{
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _now: UnixTime) -> Result<ClientCertVerified, tokio_rustls::rustls::Error> {
+        self.check(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32], CertError>{
+    let hex : Vec<u8> = fingerprint.bytes().filter(|c| *c != b':' && !c.is_ascii_whitespace()).collect();
+    let invalid = || CertError::InvalidFingerprint(fingerprint.to_string());
+    if hex.len() != 64 || !hex.iter().all(u8::is_ascii_hexdigit){
+        return Err(invalid())
+    }
+
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.chunks(2)){
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+    Ok(digest)
+}
+
+/// Validity period of certificates created by [generate_self_signed]
+#[cfg(feature = "self-signed")]
+pub const SELF_SIGNED_VALIDITY: std::time::Duration = std::time::Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// PEM encoded certificate, see [generate_self_signed]
+#[cfg(feature = "self-signed")]
+#[derive(Debug, Clone)]
+pub struct CertificatePem(pub String);
+
+/// PEM encoded private key, see [generate_self_signed]
+#[cfg(feature = "self-signed")]
+#[derive(Debug, Clone)]
+pub struct PrivateKeyPem(pub String);
+
+/// Errors of the certificate helpers
+#[derive(Debug)]
+pub enum CertError{
+    /// Invalid subject alt name or key generation failed, see [generate_self_signed]
+    #[cfg(feature = "self-signed")]
+    Generation(rcgen::Error),
+    /// Pinned fingerprint isn't a hex encoded SHA-256 digest, see [PinnedCertVerifier::new]
+    InvalidFingerprint(String),
+    /// Fingerprint of the peers certificate doesn't match any of the pinned ones
+    FingerprintMismatch{
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for CertError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "self-signed")]
+            CertError::Generation(e) => write!(f, "Couldn't generate certificate: {}", e),
+            CertError::InvalidFingerprint(fingerprint) => write!(f, "{} is not a valid SHA-256 fingerprint.", fingerprint),
+            CertError::FingerprintMismatch{actual} => write!(f, "Certificate fingerprint {} doesn't match any pinned fingerprint.", actual),
+        }
+    }
+}
+
+impl std::error::Error for CertError{}
+
+#[cfg(feature = "self-signed")]
+impl From<rcgen::Error> for CertError{
+    fn from(value: rcgen::Error) -> Self {
+        CertError::Generation(value)
+    }
+}
+
+/// Generates a self-signed certificate with a new ECDSA P-256 key, e.g. to bootstrap a rendering server
+///
+/// `subject_alt_names` are the host names (or IP addresses) the server is reached by, the first one is also used as common name.
+/// The certificate is valid from now for [SELF_SIGNED_VALIDITY] (10 years).
+/// To persist it, write both PEMs to files (e.g. with [std::fs::write]) and load them with [load_client_cert] and [load_private_key].
+/// Keep the key file readable only by the server.
+#[cfg(feature = "self-signed")]
+pub fn generate_self_signed(subject_alt_names: &[String]) -> Result<(CertificatePem, PrivateKeyPem), CertError>{
+    let mut params = rcgen::CertificateParams::new(subject_alt_names.to_vec())?;
+    if let Some(name) = subject_alt_names.first(){
+        params.distinguished_name.push(rcgen::DnType::CommonName, name.as_str());
+    }
+    params.not_before = time::OffsetDateTime::now_utc();
+    params.not_after = params.not_before + SELF_SIGNED_VALIDITY;
+
+    let key = rcgen::KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+
+    Ok((CertificatePem(cert.pem()), PrivateKeyPem(key.serialize_pem())))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn verify(verifier: &PinnedCertVerifier, cert: &CertificateDer) -> Result<ServerCertVerified, tokio_rustls::rustls::Error>{
+        let server_name = ServerName::try_from("rendering.example").unwrap();
+        verifier.verify_server_cert(cert, &[], &server_name, &[], UnixTime::now())
+    }
+
+    #[test]
+    fn wrong_fingerprint_is_rejected(){
+        // Only the digest of the certificate is compared, so it doesn't have to be valid DER
+        let pinned = CertificateDer::from(b"pinned certificate".to_vec());
+        let other = CertificateDer::from(b"other certificate".to_vec());
+        let verifier = PinnedCertVerifier::new(&[&fingerprint(&pinned).to_lowercase()]).unwrap();
+
+        assert!(verify(&verifier, &pinned).is_ok());
+        assert!(verifier.verify_client_cert(&pinned, &[], UnixTime::now()).is_ok());
+
+        for error in [verify(&verifier, &other).err(), verifier.verify_client_cert(&other, &[], UnixTime::now()).err()]{
+            let Some(tokio_rustls::rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(error)))) = error else {
+                panic!("Certificate wasn't rejected with a fingerprint mismatch: {:?}", error)
+            };
+            assert!(matches!(error.downcast_ref::<CertError>(), Some(CertError::FingerprintMismatch{actual}) if *actual == fingerprint(&other)));
+        }
+    }
+}
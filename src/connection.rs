@@ -0,0 +1,354 @@
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::rustls::pki_types::ServerName;
+use crate::dedup::{DedupedTemplateDataResult, DEDUP_PROTOCOL_VERSION};
+use crate::streaming::{ResultChunk, ResultTransferState};
+use crate::{close_connection, negotiate_version, read_message_cancellable, read_message_with_config, send_goodbye, send_message_cancellable, send_message_with_config, BatchRenderingRequest, CancelRenderingRequest, CommunicationError, Hello, Message, RenderingLog, RenderingRequest, RenderingStatusUpdate, ResumeRequest, TemplateDataRequest, TemplateDataResult, TemplateDeltaRequest, TemplateDeltaResult, TemplateListResult, TlsConnection, TransportConfig, TransportError};
+
+/// Connection to a peer which owns the stream and sends and receives whole [Message]s
+///
+/// Wraps the free functions ([send_message_with_config], [read_message_with_config], [negotiate_version], ...) with the
+/// [TransportConfig] of the connection, so it doesn't have to be passed to every call. Use [Connection::get_mut] or
+/// [Connection::into_inner] to call the free functions directly, e.g. [crate::streaming::send_result_streamed].
+pub struct Connection<S = TlsConnection>{
+    stream: S,
+    config: TransportConfig,
+    protocol_version: Option<u32>,
+}
+
+impl Connection<TlsConnection>{
+    /// Opens a TLS connection to a rendering server (see [crate::connect]) and exchanges the [Message::Hello]
+    pub async fn connect(addr: &str, server_name: ServerName<'static>, config: Arc<ClientConfig>) -> Result<Self, TransportError>{
+        let stream = crate::connect(addr, server_name, config).await?;
+        let mut connection = Connection::new(stream);
+        connection.handshake().await?;
+
+        Ok(connection)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S>{
+    /// Wraps an already connected stream with the default [TransportConfig], no messages are exchanged
+    pub fn new(stream: S) -> Self{
+        Self::with_config(stream, TransportConfig::default())
+    }
+
+    /// Wraps an already connected stream, all messages are sent and received with `config`
+    pub fn with_config(stream: S, config: TransportConfig) -> Self{
+        Connection{
+            stream,
+            config,
+            protocol_version: None,
+        }
+    }
+
+    /// Exchanges the [Message::Hello] with the peer, see [negotiate_version]
+    /// Has to be the first thing both sides do after connecting, returns the negotiated protocol version.
+    /// The following messages are encoded and decoded in the layout of that version, see [TransportConfig::protocol_version].
+    pub async fn handshake(&mut self) -> Result<u32, TransportError>{
+        let version = negotiate_version(&mut self.stream).await?;
+        self.protocol_version = Some(version);
+        self.config.protocol_version = version;
+
+        Ok(version)
+    }
+
+    /// Protocol version negotiated by [Connection::handshake], None if there was no handshake on this connection
+    pub fn protocol_version(&self) -> Option<u32>{
+        self.protocol_version
+    }
+
+    pub fn config(&self) -> &TransportConfig{
+        &self.config
+    }
+
+    /// Config used for the following messages, e.g. to enable compression after the handshake
+    pub fn config_mut(&mut self) -> &mut TransportConfig{
+        &mut self.config
+    }
+
+    /// Sends the message, returns the number of bytes written, see [send_message_with_config]
+    pub async fn send(&mut self, message: Message) -> Result<usize, TransportError>{
+        send_message_with_config(&mut self.stream, message, &self.config).await
+    }
+
+    /// Receives the next message, heartbeats are skipped, see [read_message_with_config]
+    /// A [Message::Goodbye] of the peer is returned as [TransportError::PeerClosed].
+    pub async fn recv(&mut self) -> Result<Message, TransportError>{
+        let (message, _) = read_message_with_config(&mut self.stream, &self.config).await?;
+        Ok(message)
+    }
+
+    /// Same as [Connection::send], but gives up with [TransportError::Cancelled] as soon as `cancel` completes, see [send_message_cancellable]
+    pub async fn send_cancellable<C: Future>(&mut self, message: Message, cancel: C) -> Result<usize, TransportError>{
+        send_message_cancellable(&mut self.stream, message, &self.config, cancel).await
+    }
+
+    /// Same as [Connection::recv], but gives up with [TransportError::Cancelled] as soon as `cancel` completes, see [read_message_cancellable]
+    pub async fn recv_cancellable<C: Future>(&mut self, cancel: C) -> Result<Message, TransportError>{
+        let (message, _) = read_message_cancellable(&mut self.stream, &self.config, cancel).await?;
+        Ok(message)
+    }
+
+    /// Receives the next message and returns its content if it is the expected variant, e.g. `recv_expect::<TemplateDataResult>()`
+    ///
+    /// A [Message::CommunicationError] sent by the peer is returned as [TransportError::Communication],
+    /// any other variant as [CommunicationError::UnexpectedMessageType] (the message is dropped).
+    pub async fn recv_expect<T: FromMessage>(&mut self) -> Result<T, TransportError>{
+        let message = match self.recv().await?{
+            Message::CommunicationError(e) => return Err(TransportError::Communication(e)),
+            message => message,
+        };
+
+        let message_type = message.name();
+        T::from_message(message).ok_or_else(|| {
+            tracing::warn!(message_type, expected = std::any::type_name::<T>(), "Received unexpected message");
+            TransportError::Communication(CommunicationError::UnexpectedMessageType)
+        })
+    }
+
+    /// Sends the template data, as [Message::DedupedTemplateDataResult] if the negotiated protocol version supports it
+    /// Without a [Connection::handshake] the plain [Message::TemplateDataResult] is sent.
+    pub async fn send_template_data(&mut self, result: TemplateDataResult) -> Result<usize, TransportError>{
+        let message = match self.protocol_version{
+            Some(version) if version >= DEDUP_PROTOCOL_VERSION => Message::DedupedTemplateDataResult(result.into()),
+            _ => Message::TemplateDataResult(result),
+        };
+
+        self.send(message).await
+    }
+
+    /// Receives the [TemplateDataResult] answering `request`, which has to be sent already
+    /// A [Message::DedupedTemplateDataResult] is accepted as well and restored, referenced contents missing in it fail with [TransportError::Io].
+    /// Fails with [CommunicationError::WrongTemplateDataSend] if the peer sent another template or version, see [TemplateDataResult::matches_request].
+    pub async fn recv_template_data(&mut self, request: &TemplateDataRequest) -> Result<TemplateDataResult, TransportError>{
+        let result = match self.recv_expect::<TemplateData>().await?{
+            TemplateData::Plain(result) => result,
+            TemplateData::Deduped(result) => result.try_into()?,
+        };
+        if !result.matches_request(request){
+            tracing::warn!(template_id = %result.template_id, template_version_id = %result.template_version_id,
+                expected_template_id = %request.template_id, expected_template_version_id = %request.template_version_id, "Received wrong template data");
+            return Err(TransportError::Communication(CommunicationError::WrongTemplateDataSend))
+        }
+
+        Ok(result)
+    }
+
+    /// Sends the request and receives its answer, see [Connection::recv_template_data]
+    pub async fn request_template_data(&mut self, request: TemplateDataRequest) -> Result<TemplateDataResult, TransportError>{
+        self.send(Message::TemplateDataRequest(request.clone())).await?;
+        self.recv_template_data(&request).await
+    }
+
+    /// Sends a [Message::Goodbye] and closes the connection, see [send_goodbye]
+    pub async fn goodbye(&mut self, reason: &str) -> Result<(), TransportError>{
+        send_goodbye(&mut self.stream, reason).await
+    }
+
+    /// Flushes and closes the write side of the connection, see [close_connection]
+    pub async fn close(&mut self) -> Result<(), TransportError>{
+        close_connection(&mut self.stream).await
+    }
+
+    pub fn get_ref(&self) -> &S{
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S{
+        &mut self.stream
+    }
+
+    /// Returns the stream, e.g. to hand it to a [crate::pool::RenderingServerPool]
+    pub fn into_inner(self) -> S{
+        self.stream
+    }
+}
+
+/// Content of a [Message] variant, used by [Connection::recv_expect]
+pub trait FromMessage: Sized{
+    /// Returns the content if the message is the variant carrying `Self`, None otherwise
+    fn from_message(message: Message) -> Option<Self>;
+}
+
+/// Template data in either encoding, received by [Connection::recv_template_data]
+enum TemplateData{
+    Plain(TemplateDataResult),
+    Deduped(DedupedTemplateDataResult),
+}
+
+impl FromMessage for TemplateData{
+    fn from_message(message: Message) -> Option<Self>{
+        match message{
+            Message::TemplateDataResult(result) => Some(TemplateData::Plain(result)),
+            Message::DedupedTemplateDataResult(result) => Some(TemplateData::Deduped(result)),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from_message {
+    ($($variant:ident($content:ty)),* $(,)?) => {
+        $(
+            impl FromMessage for $content{
+                fn from_message(message: Message) -> Option<Self>{
+                    match message{
+                        Message::$variant(content) => Some(content),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_message!(
+    RenderingRequest(RenderingRequest),
+    TemplateDataRequest(TemplateDataRequest),
+    TemplateDataResult(TemplateDataResult),
+    RenderingRequestStatus(RenderingStatusUpdate),
+    RenderingLog(RenderingLog),
+    ResultChunk(ResultChunk),
+    Hello(Hello),
+    CancelRenderingRequest(CancelRenderingRequest),
+    TemplateListResult(TemplateListResult),
+    TemplateDeltaRequest(TemplateDeltaRequest),
+    TemplateDeltaResult(TemplateDeltaResult),
+    ResumeRequest(ResumeRequest),
+    BatchRenderingRequest(BatchRenderingRequest),
+    ResumeResultTransfer(ResultTransferState),
+    DedupedTemplateDataResult(DedupedTemplateDataResult),
+);
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use tokio::io::DuplexStream;
+    use crate::tests::{file, folder};
+    use crate::TemplateContents;
+
+    fn template_data(request: &TemplateDataRequest) -> TemplateDataResult{
+        TemplateDataResult{
+            request_id: request.request_id,
+            template_id: request.template_id,
+            template_version_id: request.template_version_id,
+            contents: TemplateContents{
+                contents: vec![
+                    file("logo.svg", b"<svg/>"),
+                    folder("print", vec![
+                        file("logo.svg", b"<svg/>"),
+                    ]),
+                ],
+            },
+            export_formats: HashMap::new(),
+        }
+    }
+
+    fn template_request() -> TemplateDataRequest{
+        TemplateDataRequest{
+            request_id: uuid::Uuid::new_v4(),
+            template_id: uuid::Uuid::new_v4(),
+            template_version_id: uuid::Uuid::new_v4(),
+        }
+    }
+
+    async fn connected_pair() -> (Connection<DuplexStream>, Connection<DuplexStream>){
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        let (mut local, mut remote) = (Connection::new(local), Connection::new(remote));
+        let (local_version, remote_version) = tokio::join!(local.handshake(), remote.handshake());
+        assert_eq!(local_version.unwrap(), crate::PROTOCOL_VERSION);
+        assert_eq!(remote_version.unwrap(), crate::PROTOCOL_VERSION);
+
+        (local, remote)
+    }
+
+    #[tokio::test]
+    async fn template_data_is_sent_deduped(){
+        let (mut client, mut server) = connected_pair().await;
+        let request = template_request();
+
+        server.send_template_data(template_data(&request)).await.unwrap();
+        match client.recv().await.unwrap(){
+            Message::DedupedTemplateDataResult(result) => assert_eq!(result.contents.blobs.len(), 1),
+            message => panic!("Received {} instead of DedupedTemplateDataResult", message.name()),
+        }
+
+        server.send_template_data(template_data(&request)).await.unwrap();
+        let result = client.recv_template_data(&request).await.unwrap();
+        assert_eq!(result.request_id, request.request_id);
+        assert_eq!(result.contents, template_data(&request).contents);
+    }
+
+    #[tokio::test]
+    async fn template_data_without_handshake_is_sent_plain(){
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        let (mut client, mut server) = (Connection::new(local), Connection::new(remote));
+        let request = template_request();
+
+        server.send_template_data(template_data(&request)).await.unwrap();
+        match client.recv().await.unwrap(){
+            Message::TemplateDataResult(result) => assert_eq!(result.contents, template_data(&request).contents),
+            message => panic!("Received {} instead of TemplateDataResult", message.name()),
+        }
+    }
+
+    #[tokio::test]
+    async fn template_data_has_to_match_the_request(){
+        let (mut client, mut server) = connected_pair().await;
+        let request = template_request();
+
+        server.send_template_data(template_data(&request)).await.unwrap();
+        let result = client.recv_template_data(&request).await.unwrap();
+        assert_eq!((result.template_id, result.template_version_id), (request.template_id, request.template_version_id));
+
+        let other_template = TemplateDataRequest{
+            template_id: uuid::Uuid::new_v4(),
+            ..request.clone()
+        };
+        let other_version = TemplateDataRequest{
+            template_version_id: uuid::Uuid::new_v4(),
+            ..request.clone()
+        };
+        for sent in [other_template, other_version]{
+            server.send(Message::TemplateDataResult(template_data(&sent))).await.unwrap();
+            let result = client.recv_template_data(&request).await;
+            assert!(matches!(result, Err(TransportError::Communication(CommunicationError::WrongTemplateDataSend))));
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_while_peer_is_silent(){
+        // Small buffer, so sending blocks once it is full because the peer never reads
+        let (local, _remote) = tokio::io::duplex(64);
+        let mut connection = Connection::new(local);
+        let cancel_after = || tokio::time::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        let sent = tokio::time::timeout(Duration::from_secs(5), connection.send_cancellable(Message::UnexpectedError("x".repeat(1024)), cancel_after())).await;
+        assert!(matches!(sent, Ok(Err(TransportError::Cancelled))), "{:?}", sent.map(|sent| sent.err()));
+
+        let received = tokio::time::timeout(Duration::from_secs(5), connection.recv_cancellable(cancel_after())).await;
+        assert!(matches!(received, Ok(Err(TransportError::Cancelled))), "{:?}", received.map(|received| received.err()));
+        assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn handshake_uses_version_of_older_peer(){
+        let (local, mut remote) = tokio::io::duplex(64 * 1024);
+        let mut connection = Connection::new(local);
+        let older = Hello{
+            protocol_version: crate::MIN_SUPPORTED_PROTOCOL_VERSION,
+            crate_version: "0.1.0".to_string(),
+        };
+
+        let (version, sent) = tokio::join!(connection.handshake(), crate::send_message(&mut remote, Message::Hello(older)));
+        sent.unwrap();
+        assert_eq!(version.unwrap(), crate::MIN_SUPPORTED_PROTOCOL_VERSION);
+        assert_eq!(connection.protocol_version(), Some(crate::MIN_SUPPORTED_PROTOCOL_VERSION));
+        assert_eq!(connection.config().protocol_version, crate::MIN_SUPPORTED_PROTOCOL_VERSION);
+    }
+}
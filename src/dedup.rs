@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use crate::export_formats::ExportFormat;
+use crate::{FileOrFolder, NamedFile, NamedFolder, NamedSymlink, TemplateContents, TemplateDataResult};
+
+/// Oldest protocol version which understands [crate::Message::DedupedTemplateDataResult]
+pub const DEDUP_PROTOCOL_VERSION: u32 = 22;
+
+/// SHA-256 digest of a file content, used as key of [DedupedTemplateContents::blobs]
+pub type BlobHash = [u8; 32];
+
+/// [TemplateContents] where every distinct file content is stored only once
+///
+/// Templates often contain the same font or logo in several folders. Converting them into this type before sending
+/// transmits each of these files once, the tree only references the content by its hash.
+#[derive(bincode::Decode, bincode::Encode, Debug, PartialEq)]
+pub struct DedupedTemplateContents{
+    /// File contents by their SHA-256 digest
+    pub blobs: HashMap<BlobHash, Vec<u8>>,
+    pub contents: Vec<DedupedFileOrFolder>,
+}
+
+#[derive(bincode::Decode, bincode::Encode, Debug, PartialEq)]
+pub enum DedupedFileOrFolder{
+    File(DedupedFile),
+    Folder(DedupedFolder),
+    Symlink(NamedSymlink),
+}
+
+/// [NamedFile] whose content is stored in [DedupedTemplateContents::blobs]
+#[derive(bincode::Decode, bincode::Encode, Debug, PartialEq)]
+pub struct DedupedFile{
+    pub name: String,
+    pub hash: BlobHash,
+    /// See [NamedFile::mode]
+    pub mode: Option<u32>,
+    /// See [NamedFile::modified]
+    pub modified: Option<std::time::SystemTime>,
+}
+
+#[derive(bincode::Decode, bincode::Encode, Debug, PartialEq)]
+pub struct DedupedFolder{
+    pub name: String,
+    pub contents: Vec<DedupedFileOrFolder>,
+}
+
+/// [TemplateDataResult] with [DedupedTemplateContents], sent as [crate::Message::DedupedTemplateDataResult]
+///
+/// Sent instead of the plain result if the peer speaks at least [DEDUP_PROTOCOL_VERSION], see [crate::connection::Connection::send_template_data].
+#[derive(bincode::Decode, bincode::Encode)]
+pub struct DedupedTemplateDataResult{
+    /// See [TemplateDataResult::request_id]
+    #[bincode(with_serde)]
+    pub request_id: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub template_id: uuid::Uuid,
+    #[bincode(with_serde)]
+    pub template_version_id: uuid::Uuid,
+    pub contents: DedupedTemplateContents,
+    pub export_formats: HashMap<String, ExportFormat>
+}
+
+impl From<TemplateDataResult> for DedupedTemplateDataResult{
+    fn from(result: TemplateDataResult) -> Self {
+        DedupedTemplateDataResult{
+            request_id: result.request_id,
+            template_id: result.template_id,
+            template_version_id: result.template_version_id,
+            contents: result.contents.into(),
+            export_formats: result.export_formats,
+        }
+    }
+}
+
+/// Restores the plain [TemplateDataResult], see [TemplateContents::try_from]
+impl TryFrom<DedupedTemplateDataResult> for TemplateDataResult{
+    type Error = std::io::Error;
+
+    fn try_from(result: DedupedTemplateDataResult) -> Result<Self, Self::Error> {
+        Ok(TemplateDataResult{
+            request_id: result.request_id,
+            template_id: result.template_id,
+            template_version_id: result.template_version_id,
+            contents: result.contents.try_into()?,
+            export_formats: result.export_formats,
+        })
+    }
+}
+
+impl DedupedTemplateContents{
+    /// Total size of all distinct file contents in bytes
+    pub fn blob_bytes(&self) -> u64{
+        self.blobs.values().map(|blob| blob.len() as u64).sum()
+    }
+}
+
+impl From<TemplateContents> for DedupedTemplateContents{
+    fn from(template: TemplateContents) -> Self {
+        let mut blobs = HashMap::new();
+        let contents = dedup_tree(template.contents, &mut blobs);
+
+        DedupedTemplateContents{
+            blobs,
+            contents,
+        }
+    }
+}
+
+/// Restores the plain [TemplateContents], files with the same content get their own copy again
+/// Fails with [std::io::ErrorKind::InvalidData] if a file references a hash which isn't in the blobs.
+impl TryFrom<DedupedTemplateContents> for TemplateContents{
+    type Error = std::io::Error;
+
+    fn try_from(template: DedupedTemplateContents) -> Result<Self, Self::Error> {
+        Ok(TemplateContents{
+            contents: restore_tree(template.contents, &template.blobs)?,
+        })
+    }
+}
+
+fn dedup_tree(contents: Vec<FileOrFolder>, blobs: &mut HashMap<BlobHash, Vec<u8>>) -> Vec<DedupedFileOrFolder>{
+    contents.into_iter().map(|item| match item{
+        FileOrFolder::File(file) => {
+            let hash : BlobHash = Sha256::digest(&file.content).into();
+            blobs.entry(hash).or_insert(file.content);
+
+            DedupedFileOrFolder::File(DedupedFile{
+                name: file.name,
+                hash,
+                mode: file.mode,
+                modified: file.modified,
+            })
+        },
+        FileOrFolder::Folder(folder) => DedupedFileOrFolder::Folder(DedupedFolder{
+            name: folder.name,
+            contents: dedup_tree(folder.contents, blobs),
+        }),
+        FileOrFolder::Symlink(symlink) => DedupedFileOrFolder::Symlink(symlink),
+    }).collect()
+}
+
+fn restore_tree(contents: Vec<DedupedFileOrFolder>, blobs: &HashMap<BlobHash, Vec<u8>>) -> std::io::Result<Vec<FileOrFolder>>{
+    contents.into_iter().map(|item| Ok(match item{
+        DedupedFileOrFolder::File(file) => {
+            let content = blobs.get(&file.hash).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Content of {} is missing", file.name)))?;
+
+            FileOrFolder::File(NamedFile{
+                name: file.name,
+                content: content.clone(),
+                mode: file.mode,
+                modified: file.modified,
+            })
+        },
+        DedupedFileOrFolder::Folder(folder) => FileOrFolder::Folder(NamedFolder{
+            name: folder.name,
+            contents: restore_tree(folder.contents, blobs)?,
+        }),
+        DedupedFileOrFolder::Symlink(symlink) => FileOrFolder::Symlink(symlink),
+    })).collect()
+}
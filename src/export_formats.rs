@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::fmt;
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
@@ -10,6 +11,33 @@ pub enum ExportStepData {
     Pandoc(PandocExportStep),
 }
 
+impl ExportStepData {
+    /// Files the step reads, either template files or files generated by a previous step
+    pub fn input_files(&self) -> Vec<&str> {
+        match self {
+            ExportStepData::Raw(step) => vec![step.entry_point.as_str()],
+            ExportStepData::Vivliostyle(step) => vec![step.input_file.as_str()],
+            ExportStepData::Pandoc(step) => {
+                let mut files = vec![step.input_file.as_str()];
+                files.extend(step.metadata_file.as_deref());
+                files.extend(step.epub_cover_image_path.as_deref());
+                files.extend(step.epub_metadata_file.as_deref());
+                files.extend(step.epub_embed_fonts.iter().flatten().map(String::as_str));
+                files
+            }
+        }
+    }
+
+    /// File generated by the step
+    pub fn output_file(&self) -> &str {
+        match self {
+            ExportStepData::Raw(step) => &step.output_file,
+            ExportStepData::Vivliostyle(step) => &step.output_file,
+            ExportStepData::Pandoc(step) => &step.output_file,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone)]
 pub struct ExportStep{
     /// id should only be None for not yet saved ExportSteps
@@ -316,4 +344,161 @@ pub struct ExportFormat{
     pub output_files: Vec<String>,
     /// Optional path to a pdf used as preview. Make sure to use the path to an output file created by one of the export steps
     pub preview_pdf_path: Option<String>
-}
\ No newline at end of file
+}
+
+impl ExportFormat {
+    /// Checks that the export steps form a working pipeline, so e.g. a [crate::RenderingError::MissingExpectedFileToKeep] is caught before rendering
+    ///
+    /// The steps are run in order. Each step generates its output file, afterwards all generated files which aren't in
+    /// its files_to_keep are pruned. Template files are never pruned, so inputs which aren't generated by any step are assumed to be template files.
+    /// Returns all issues found, in the order of the steps.
+    pub fn validate(&self) -> Result<(), Vec<ExportFormatIssue>> {
+        let mut issues = Vec::new();
+        if self.export_steps.is_empty() {
+            issues.push(ExportFormatIssue::NoSteps);
+        }
+
+        let generated_by_any: HashSet<&str> = self.export_steps.iter().map(|step| step.data.output_file()).collect();
+        // Generated files present before the current step
+        let mut available: HashSet<&str> = HashSet::new();
+
+        for step in self.export_steps.iter() {
+            for input in step.data.input_files() {
+                if generated_by_any.contains(input) && !available.contains(input) {
+                    issues.push(ExportFormatIssue::InputNotAvailable { step: step.name.clone(), file: input.to_string() });
+                }
+            }
+
+            available.insert(step.data.output_file());
+            for file in step.files_to_keep.iter() {
+                if !available.contains(file.as_str()) {
+                    issues.push(ExportFormatIssue::MissingFileToKeep { step: step.name.clone(), file: file.clone() });
+                }
+            }
+            available.retain(|file| step.files_to_keep.iter().any(|keep| keep == file));
+        }
+
+        for file in self.output_files.iter() {
+            if !available.contains(file.as_str()) {
+                issues.push(ExportFormatIssue::MissingOutputFile(file.clone()));
+            }
+        }
+        if let Some(preview) = &self.preview_pdf_path {
+            if !available.contains(preview.as_str()) {
+                issues.push(ExportFormatIssue::MissingPreviewPdf(preview.clone()));
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
+
+/// Problem found by [ExportFormat::validate]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportFormatIssue {
+    /// The export format has no export steps
+    NoSteps,
+    /// The step reads a file which is generated by another step, but that step runs later or the file was pruned before
+    InputNotAvailable { step: String, file: String },
+    /// The step should keep a file which isn't generated by it and wasn't kept by the previous step
+    MissingFileToKeep { step: String, file: String },
+    /// The output file isn't kept by the last step
+    MissingOutputFile(String),
+    /// The preview pdf isn't kept by the last step
+    MissingPreviewPdf(String),
+}
+
+impl fmt::Display for ExportFormatIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportFormatIssue::NoSteps => write!(f, "The export format has no export steps."),
+            ExportFormatIssue::InputNotAvailable { step, file } => write!(f, "Export step {} reads {}, which isn't available at this point.", step, file),
+            ExportFormatIssue::MissingFileToKeep { step, file } => write!(f, "Export step {} should keep {}, which doesn't exist at this point.", step, file),
+            ExportFormatIssue::MissingOutputFile(file) => write!(f, "The output file {} isn't kept by the export steps.", file),
+            ExportFormatIssue::MissingPreviewPdf(file) => write!(f, "The preview pdf {} isn't kept by the export steps.", file),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_step(name: &str, entry_point: &str, output_file: &str, files_to_keep: &[&str]) -> ExportStep {
+        ExportStep {
+            id: None,
+            name: name.to_string(),
+            data: ExportStepData::Raw(RawExportStep {
+                entry_point: entry_point.to_string(),
+                output_file: output_file.to_string(),
+            }),
+            files_to_keep: files_to_keep.iter().map(|file| file.to_string()).collect(),
+        }
+    }
+
+    fn vivliostyle_step(name: &str, input_file: &str, output_file: &str, files_to_keep: &[&str]) -> ExportStep {
+        ExportStep {
+            id: None,
+            name: name.to_string(),
+            data: ExportStepData::Vivliostyle(VivliostyleExportStep {
+                press_ready: true,
+                input_file: input_file.to_string(),
+                output_file: output_file.to_string(),
+            }),
+            files_to_keep: files_to_keep.iter().map(|file| file.to_string()).collect(),
+        }
+    }
+
+    fn pdf_format(html_kept: &[&str]) -> ExportFormat {
+        ExportFormat {
+            slug: "pdf".to_string(),
+            name: "PDF".to_string(),
+            export_steps: vec![
+                raw_step("html", "main.hbs", "book.html", html_kept),
+                vivliostyle_step("pdf", "book.html", "book.pdf", &["book.pdf"]),
+            ],
+            output_files: vec!["book.pdf".to_string()],
+            preview_pdf_path: Some("book.pdf".to_string()),
+        }
+    }
+
+    #[test]
+    fn valid_pipeline() {
+        assert_eq!(pdf_format(&["book.html"]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn pruned_input() {
+        assert_eq!(pdf_format(&[]).validate(), Err(vec![
+            ExportFormatIssue::InputNotAvailable { step: "pdf".to_string(), file: "book.html".to_string() },
+        ]));
+    }
+
+    #[test]
+    fn missing_output_files() {
+        let mut format = pdf_format(&["book.html"]);
+        format.output_files.push("book.html".to_string());
+        format.preview_pdf_path = Some("preview.pdf".to_string());
+
+        assert_eq!(format.validate(), Err(vec![
+            ExportFormatIssue::MissingOutputFile("book.html".to_string()),
+            ExportFormatIssue::MissingPreviewPdf("preview.pdf".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn no_steps() {
+        let mut format = pdf_format(&["book.html"]);
+        format.export_steps.clear();
+        format.preview_pdf_path = None;
+
+        assert_eq!(format.validate(), Err(vec![
+            ExportFormatIssue::NoSteps,
+            ExportFormatIssue::MissingOutputFile("book.pdf".to_string()),
+        ]));
+    }
+}
@@ -0,0 +1,167 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::rustls::pki_types::ServerName;
+use crate::{connect, negotiate_version, read_message, send_message, Message, TlsConnection, TransportConfig, TransportError};
+
+/// How long a pooled connection may take to answer a [Message::Ping] before it is considered broken
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Address of a rendering server
+#[derive(Clone, Debug)]
+pub struct RenderingServer{
+    /// Address to connect to, e.g. "render.example.org:9000"
+    pub addr: String,
+    /// Name the server certificate is checked against
+    pub server_name: ServerName<'static>,
+}
+
+/// Keeps a bounded set of TLS connections to a set of rendering servers to reuse them for multiple requests
+///
+/// Connections are handed out by [RenderingServerPool::acquire] and returned to the pool when the [PooledConnection] is dropped.
+/// Before an idle connection is reused, it is checked with a [Message::Ping], broken connections are discarded.
+/// New connections are opened round-robin over the servers and exchange the [Message::Hello] (see [negotiate_version]) before they are handed out,
+/// the negotiated version is kept with the connection.
+pub struct RenderingServerPool{
+    servers: Vec<RenderingServer>,
+    config: Arc<ClientConfig>,
+    idle: Arc<Mutex<Vec<(TlsConnection, u32)>>>,
+    permits: Arc<Semaphore>,
+    next_server: AtomicUsize,
+}
+
+impl RenderingServerPool{
+    /// Creates a new pool, which keeps at most `max_connections` connections open.
+    pub fn new(servers: Vec<RenderingServer>, config: Arc<ClientConfig>, max_connections: usize) -> Self{
+        Self{
+            servers,
+            config,
+            idle: Arc::new(Mutex::new(Vec::new())),
+            permits: Arc::new(Semaphore::new(max_connections)),
+            next_server: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns a working connection, waits if all connections are in use.
+    /// Reuses an idle connection if one answers a ping, otherwise opens a new connection.
+    /// Fails with the error of the last server if none could be connected to, e.g. [TransportError::IncompatibleProtocolVersion].
+    pub async fn acquire(&self) -> Result<PooledConnection, TransportError>{
+        let permit = self.permits.clone().acquire_owned().await.map_err(tokio::io::Error::other)?;
+
+        loop {
+            let idle = self.idle.lock().unwrap().pop();
+            match idle {
+                Some((mut stream, protocol_version)) => {
+                    if is_alive(&mut stream).await{
+                        return Ok(self.pooled(stream, protocol_version, permit))
+                    }
+                },
+                None => break
+            }
+        }
+
+        let (stream, protocol_version) = self.connect_any().await?;
+        Ok(self.pooled(stream, protocol_version, permit))
+    }
+
+    /// Tries to connect to each server once, starting with the next one in round-robin order
+    /// Returns the connection with the protocol version negotiated with the server.
+    async fn connect_any(&self) -> Result<(TlsConnection, u32), TransportError>{
+        let mut last_error = TransportError::Io(tokio::io::Error::other("No rendering servers configured"));
+
+        for _ in 0..self.servers.len(){
+            let server = &self.servers[self.next_server.fetch_add(1, Ordering::Relaxed) % self.servers.len()];
+            match connect_server(server, self.config.clone()).await{
+                Ok(connection) => return Ok(connection),
+                Err(e) => {
+                    tracing::warn!(server = %server.addr, error = %e, "Couldn't connect to rendering server");
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn pooled(&self, stream: TlsConnection, protocol_version: u32, permit: OwnedSemaphorePermit) -> PooledConnection{
+        PooledConnection{
+            stream: Some(stream),
+            protocol_version,
+            idle: self.idle.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+/// Connects to the server and exchanges the [Message::Hello], returns the connection with the negotiated protocol version
+async fn connect_server(server: &RenderingServer, config: Arc<ClientConfig>) -> Result<(TlsConnection, u32), TransportError>{
+    let mut stream = connect(&server.addr, server.server_name.clone(), config).await?;
+    let protocol_version = negotiate_version(&mut stream).await?;
+
+    Ok((stream, protocol_version))
+}
+
+async fn is_alive(stream: &mut TlsConnection) -> bool{
+    if send_message(stream, Message::Ping).await.is_err(){
+        return false
+    }
+
+    matches!(time::timeout(PING_TIMEOUT, read_message(stream)).await, Ok(Ok((Message::Pong, _))))
+}
+
+/// Connection handed out by [RenderingServerPool::acquire], derefs to the underlying [TlsConnection]
+///
+/// Returned to the pool when dropped. Call [PooledConnection::discard] instead if the connection is in an unknown state
+/// (e.g. an exchange was aborted halfway), so it doesn't get reused.
+pub struct PooledConnection{
+    stream: Option<TlsConnection>,
+    protocol_version: u32,
+    idle: Arc<Mutex<Vec<(TlsConnection, u32)>>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection{
+    /// Protocol version negotiated with the rendering server when the connection was opened, see [negotiate_version]
+    pub fn protocol_version(&self) -> u32{
+        self.protocol_version
+    }
+
+    /// Default [TransportConfig] with the negotiated protocol version, to send and receive messages on this connection
+    pub fn transport_config(&self) -> TransportConfig{
+        TransportConfig{
+            protocol_version: self.protocol_version,
+            ..Default::default()
+        }
+    }
+
+    /// Closes the connection instead of returning it to the pool
+    pub fn discard(mut self){
+        self.stream = None;
+    }
+}
+
+impl Deref for PooledConnection{
+    type Target = TlsConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.stream.as_ref().expect("Connection already discarded")
+    }
+}
+
+impl DerefMut for PooledConnection{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.stream.as_mut().expect("Connection already discarded")
+    }
+}
+
+impl Drop for PooledConnection{
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take(){
+            self.idle.lock().unwrap().push((stream, self.protocol_version));
+        }
+    }
+}
@@ -1,8 +1,106 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::str::FromStr;
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
+use crate::export_formats::ExportFormatIssue;
 
 /// Struct holds all project-level settings
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub struct ProjectSettingsV6 {
+    pub toc_enabled: bool,
+    pub csl_style: Option<String>,
+    pub csl_language_code: Option<String>,
+    pub metadata_page_additional_html: Option<String>,
+    pub cover_image_path: Option<String>,
+    pub backcover_image_path: Option<String>,
+    /// Running heads and page numbers of printed pages, None leaves them up to the template
+    pub running_headers: Option<RunningHeaders>,
+    /// Whether fonts get embedded (subsetted) into the PDF output
+    pub embed_fonts: bool,
+    /// Path to a folder with additional fonts used by the template
+    pub custom_fonts_path: Option<String>,
+}
+
+impl From<ProjectSettingsV5> for ProjectSettingsV6{
+    fn from(settings: ProjectSettingsV5) -> Self{
+        Self{
+            toc_enabled: settings.toc_enabled,
+            csl_style: settings.csl_style,
+            csl_language_code: settings.csl_language_code,
+            metadata_page_additional_html: settings.metadata_page_additional_html,
+            cover_image_path: settings.cover_image_path,
+            backcover_image_path: settings.backcover_image_path,
+            running_headers: settings.running_headers,
+            embed_fonts: true,
+            custom_fonts_path: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub struct ProjectSettingsV5 {
+    pub toc_enabled: bool,
+    pub csl_style: Option<String>,
+    pub csl_language_code: Option<String>,
+    pub metadata_page_additional_html: Option<String>,
+    pub cover_image_path: Option<String>,
+    pub backcover_image_path: Option<String>,
+    /// Running heads and page numbers of printed pages, None leaves them up to the template
+    pub running_headers: Option<RunningHeaders>,
+}
+
+impl From<ProjectSettingsV4> for ProjectSettingsV5{
+    fn from(settings: ProjectSettingsV4) -> Self{
+        Self{
+            toc_enabled: settings.toc_enabled,
+            csl_style: settings.csl_style,
+            csl_language_code: settings.csl_language_code,
+            metadata_page_additional_html: settings.metadata_page_additional_html,
+            cover_image_path: settings.cover_image_path,
+            backcover_image_path: settings.backcover_image_path,
+            running_headers: None,
+        }
+    }
+}
+
+/// Describes the running heads of printed pages, the rendering server maps them to `@page` rules
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub struct RunningHeaders {
+    /// Content of the running head on left-hand (verso) pages, empty for no running head
+    pub verso: Vec<RunningHeaderToken>,
+    /// Content of the running head on right-hand (recto) pages, empty for no running head
+    pub recto: Vec<RunningHeaderToken>,
+    /// Where page numbers are placed, None if no page numbers should be shown
+    pub page_numbers: Option<PageNumberPosition>,
+}
+
+/// Content token of a running head, multiple tokens are joined by the template
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub enum RunningHeaderToken {
+    BookTitle,
+    /// Names of the book authors (or editors if there are no authors)
+    BookAuthors,
+    /// Title of the current section
+    SectionTitle,
+    /// Names of the authors of the current section
+    SectionAuthors,
+    /// Fixed text
+    Text(String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub enum PageNumberPosition {
+    /// In the running head, on the outer edge of the page
+    HeaderOutside,
+    HeaderCenter,
+    /// In the footer, on the outer edge of the page
+    FooterOutside,
+    FooterCenter,
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct ProjectSettingsV4 {
     pub toc_enabled: bool,
     pub csl_style: Option<String>,
@@ -25,35 +123,77 @@ impl From<ProjectSettingsV3> for ProjectSettingsV4{
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct ProjectSettingsV3 {
     pub toc_enabled: bool,
     pub csl_style: Option<String>,
     pub csl_language_code: Option<String>
 }
 
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+impl From<ProjectSettingsV2> for ProjectSettingsV3{
+    fn from(settings: ProjectSettingsV2) -> Self{
+        Self{
+            toc_enabled: settings.toc_enabled,
+            csl_style: settings.csl_style,
+            csl_language_code: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct ProjectSettingsV2 {
     pub toc_enabled: bool,
     pub csl_style: Option<String>,
 }
 
 /// Struct holds a biography in a specified language for a person
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct Biography {
     pub content: String,
     pub lang: Option<Language>,
 }
 
 /// Enum to differentiate between all supported languages
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub enum Language{
     DE,
-    EN
+    EN,
+    FR,
+    IT,
+    ES,
+    /// Any other language as lowercase ISO 639-1 code
+    Other(String),
+}
+
+impl Language{
+    /// Language of an ISO 639-1 code (case-insensitive, e.g. "de" or "FR"), unknown codes become [Language::Other]
+    pub fn from_iso_code(code: &str) -> Language{
+        let code = code.trim().to_ascii_lowercase();
+        match code.as_str(){
+            "de" => Language::DE,
+            "en" => Language::EN,
+            "fr" => Language::FR,
+            "it" => Language::IT,
+            "es" => Language::ES,
+            _ => Language::Other(code),
+        }
+    }
+
+    /// ISO 639-1 code of the language, e.g. "de"
+    pub fn to_iso_code(&self) -> &str{
+        match self{
+            Language::DE => "de",
+            Language::EN => "en",
+            Language::FR => "fr",
+            Language::IT => "it",
+            Language::ES => "es",
+            Language::Other(code) => code,
+        }
+    }
 }
 
 /// Struct holds all data for a person (e.g. author or editor)
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct Person {
     #[bincode(with_serde)]
     pub id: Option<uuid::Uuid>,
@@ -63,11 +203,144 @@ pub struct Person {
     pub gnd: Option<Identifier>,
     pub bios: Option<Vec<Biography>>,
     pub ror: Option<Identifier>,
+    /// Academic or honorific prefix, e.g. "Dr."
+    pub name_prefix: Option<String>,
+    /// Name suffix, e.g. "Jr."
+    pub name_suffix: Option<String>,
+    /// Nobiliary particle, e.g. "von" in "Armin von Bogdandy". Kept out of last_names, so the person is sorted under the last name.
+    pub particle: Option<String>,
+}
+
+impl Person{
+    /// Full name for display
+    ///
+    /// [NameOrder::FirstLast] gives e.g. "Dr. Armin von Bogdandy", [NameOrder::LastFirst] "Bogdandy, Armin von" (without the prefix).
+    /// A suffix is appended in both orders. Missing or empty parts are left out without leaving stray commas or spaces.
+    pub fn display_name(&self, order: NameOrder) -> String{
+        match order{
+            NameOrder::FirstLast => join_name_parts(&[self.name_prefix.as_deref(), self.first_names.as_deref(), self.particle.as_deref(), Some(self.last_names.as_str()), self.name_suffix.as_deref()], " "),
+            NameOrder::LastFirst => {
+                let given = join_name_parts(&[self.first_names.as_deref(), self.particle.as_deref()], " ");
+                join_name_parts(&[Some(self.last_names.as_str()), Some(given.as_str()), self.name_suffix.as_deref()], ", ")
+            }
+        }
+    }
+
+    /// Validates the ORCID, GND and ROR identifiers of the person, see [Identifier::validate]
+    /// Returns the first error, e.g. an ORCID with a wrong check digit.
+    pub fn validate_identifiers(&self) -> Result<(), IdentifierError>{
+        for identifier in [&self.orcid, &self.gnd, &self.ror].into_iter().flatten(){
+            identifier.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Lowercase "last names, first names" key to sort persons alphabetically.
+    /// Prefix, suffix and particle are left out, so "Armin von Bogdandy" is sorted under B.
+    pub fn sort_key(&self) -> String{
+        let last_names = self.last_names.trim();
+        match self.first_names.as_deref().map(str::trim){
+            Some(first_names) if !first_names.is_empty() => format!("{}, {}", last_names, first_names).to_lowercase(),
+            _ => last_names.to_lowercase(),
+        }
+    }
+
+    /// Checks whether both entries describe the same person
+    ///
+    /// Persons are the same if they have the same ORCID or the same GND (compared without formatting differences).
+    /// If neither of them has an ORCID or GND, their first names, particle and last names have to match exactly.
+    pub fn is_same_person(&self, other: &Person) -> bool{
+        if same_identifier(&self.orcid, &other.orcid) || same_identifier(&self.gnd, &other.gnd){
+            return true
+        }
+        let has_id = |person: &Person| person.orcid.is_some() || person.gnd.is_some();
+        let trimmed = |part: &Option<String>| part.as_deref().map(str::trim).map(str::to_string);
+        !has_id(self) && !has_id(other)
+            && trimmed(&self.first_names) == trimmed(&other.first_names)
+            && trimmed(&self.particle) == trimmed(&other.particle)
+            && self.last_names.trim() == other.last_names.trim()
+    }
+
+    /// Returns the biography to show for the given language
+    ///
+    /// Fallback order: the first biography in exactly this language, then the first one without a language,
+    /// then the first one at all. None if the person has no biographies.
+    pub fn bio_for(&self, lang: &Language) -> Option<&Biography>{
+        let bios = self.bios.as_deref().unwrap_or_default();
+
+        bios.iter().find(|bio| bio.lang.as_ref() == Some(lang))
+            .or_else(|| bios.iter().find(|bio| bio.lang.is_none()))
+            .or_else(|| bios.first())
+    }
+
+    /// Whether the person has a biography in exactly this language, biographies without a language don't count
+    pub fn has_bio_in(&self, lang: &Language) -> bool{
+        self.bios.iter().flatten().any(|bio| bio.lang.as_ref() == Some(lang))
+    }
+
+    /// Merges another entry of the same person into this one, see [Person::is_same_person]
+    ///
+    /// Fields already set on this entry take precedence, missing ones are taken from `other`.
+    /// Biographies of `other` are appended unless this entry already has an identical one.
+    pub fn merge(&mut self, other: Person){
+        fn fill<T>(field: &mut Option<T>, other: Option<T>){
+            if field.is_none(){
+                *field = other;
+            }
+        }
+
+        fill(&mut self.id, other.id);
+        fill(&mut self.first_names, other.first_names);
+        fill(&mut self.orcid, other.orcid);
+        fill(&mut self.gnd, other.gnd);
+        fill(&mut self.ror, other.ror);
+        fill(&mut self.name_prefix, other.name_prefix);
+        fill(&mut self.name_suffix, other.name_suffix);
+        fill(&mut self.particle, other.particle);
+
+        for bio in other.bios.into_iter().flatten(){
+            let bios = self.bios.get_or_insert_with(Vec::new);
+            if !bios.contains(&bio){
+                bios.push(bio);
+            }
+        }
+    }
+}
+
+/// Whether both identifiers are set and have the same value, ORCIDs are compared in their normalized form
+fn same_identifier(a: &Option<Identifier>, b: &Option<Identifier>) -> bool{
+    let normalized = |identifier: &Identifier| match identifier.identifier_type{
+        IdentifierType::ORCID => Identifier::normalize_orcid(&identifier.value).unwrap_or_else(|_| identifier.value.trim().to_uppercase()),
+        _ => identifier.value.trim().to_uppercase(),
+    };
+    match (a, b){
+        (Some(a), Some(b)) => normalized(a) == normalized(b),
+        _ => false,
+    }
 }
 
 
+/// Order of the name parts in [Person::display_name]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameOrder{
+    /// e.g. "Armin von Bogdandy"
+    FirstLast,
+    /// e.g. "Bogdandy, Armin von"
+    LastFirst,
+}
+
+/// Joins the trimmed, non-empty parts with the separator
+fn join_name_parts(parts: &[Option<&str>], separator: &str) -> String{
+    parts.iter()
+        .flatten()
+        .map(|part| part.trim())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join(separator)
+}
+
 /// Represents an identifier (e.g. DOI, ISBN, ISSN, URL, URN, ORCID, ROR, ...)
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub struct Identifier{
     #[bincode(with_serde)]
     pub id: Option<uuid::Uuid>,
@@ -85,34 +358,292 @@ impl Identifier{
     /// * `name` - Name of identifier as [`Option<String>`] - optional
     ///     if not given, the name of the identifier type is used
     ///
+    /// DOI and URL values are normalized, see [Identifier::normalize_value]
+    ///
     /// Returns
     /// * `Identifier` - New identifier
     pub fn new(identifier_type: IdentifierType, value: String, name: Option<String>) -> Self{
         // If no name is given, use the name of the identifier type
         let name = match name{
             Some(name) => name,
-            None => match &identifier_type{
-                IdentifierType::DOI => "DOI".to_string(),
-                IdentifierType::ISBN => "ISBN".to_string(),
-                IdentifierType::ISSN => "ISSN".to_string(),
-                IdentifierType::URL => "URL".to_string(),
-                IdentifierType::URN => "URN".to_string(),
-                IdentifierType::ORCID => "ORCID".to_string(),
-                IdentifierType::ROR => "ROR".to_string(),
-                IdentifierType::GND => "GND".to_string(),
-                IdentifierType::Other(other) => other.clone(),
-            },
+            None => identifier_type.type_name().to_string(),
         };
         Self{
             id: Some(uuid::Uuid::new_v4()),
             name,
-            value,
+            value: Self::normalize_value(&identifier_type, &value),
             identifier_type,
         }
     }
+
+    /// Returns the canonical form of an identifier value, used by [Identifier::new] and [Identifier::normalize]
+    ///
+    /// DOIs are reduced to the bare form (e.g. "doi:10.1234/foo" or "https://doi.org/10.1234/foo/" to "10.1234/foo"),
+    /// URLs are trimmed, get https:// if they lack a scheme, scheme and host are lowercased and the slash of an empty path is removed.
+    /// Empty URLs and values of all other types are returned unchanged.
+    pub fn normalize_value(identifier_type: &IdentifierType, value: &str) -> String{
+        match identifier_type{
+            IdentifierType::DOI => normalize_doi(value).to_string(),
+            IdentifierType::URL => normalize_url(value),
+            _ => value.to_string(),
+        }
+    }
+
+    /// Normalizes the value in place, see [Identifier::normalize_value]
+    pub fn normalize(&mut self){
+        self.value = Self::normalize_value(&self.identifier_type, &self.value);
+    }
+
+    /// Checks whether the value is well-formed for its identifier type
+    ///
+    /// ISBNs (ISBN-10 or ISBN-13) and ISSNs are checked including their check digit, hyphens and spaces are ignored.
+    /// ORCIDs are checked including their check digit, see [Identifier::normalize_orcid].
+    /// DOIs, URLs and URNs are checked for their basic syntax, other types are always valid.
+    /// Not called by [Identifier::new], so existing values can still be stored.
+    pub fn validate(&self) -> Result<(), IdentifierError>{
+        let value = self.value.trim();
+        let valid_format = match self.identifier_type{
+            IdentifierType::ISBN => {
+                let len = value.chars().filter(|c| *c != '-' && !c.is_whitespace()).count();
+                return match len{
+                    10 => parse_isbn10(value).map(|_| ()),
+                    13 => parse_isbn13(value).map(|_| ()),
+                    _ => Err(IdentifierError::InvalidIsbn(self.value.clone()))
+                }
+            },
+            IdentifierType::ISSN => return validate_issn(value),
+            IdentifierType::ORCID => return Self::normalize_orcid(value).map(|_| ()),
+            IdentifierType::DOI => is_valid_doi(value),
+            IdentifierType::URL => is_valid_url(value),
+            IdentifierType::URN => is_valid_urn(value),
+            IdentifierType::ROR | IdentifierType::GND | IdentifierType::Other(_) => true,
+        };
+
+        match valid_format{
+            true => Ok(()),
+            false => Err(IdentifierError::InvalidFormat(self.identifier_type.clone(), self.value.clone()))
+        }
+    }
+
+    /// Normalizes an ORCID to the form 0000-0002-1825-0097 and validates its MOD 11-2 check digit
+    ///
+    /// Accepts the ORCID with or without hyphens, optionally as URL (e.g. https://orcid.org/0000-0002-1825-0097).
+    pub fn normalize_orcid(value: &str) -> Result<String, IdentifierError>{
+        let trimmed = value.trim();
+        let orcid = strip_prefix_ignore_case(trimmed, &["https://orcid.org/", "http://orcid.org/", "orcid.org/"]).unwrap_or(trimmed);
+
+        let chars : Vec<char> = orcid.chars().filter(|c| *c != '-' && !c.is_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+        if chars.len() != 16 || !chars[..15].iter().all(char::is_ascii_digit) || !(chars[15].is_ascii_digit() || chars[15] == 'X'){
+            return Err(IdentifierError::InvalidFormat(IdentifierType::ORCID, value.to_string()))
+        }
+
+        let total = chars[..15].iter().fold(0, |total, c| (total + c.to_digit(10).unwrap_or(0)) * 2);
+        let check_digit = match (12 - total % 11) % 11{
+            10 => 'X',
+            digit => char::from(b'0' + digit as u8),
+        };
+        if check_digit != chars[15]{
+            return Err(IdentifierError::InvalidChecksum(value.to_string()))
+        }
+
+        Ok(chars.chunks(4).map(|block| block.iter().collect::<String>()).collect::<Vec<String>>().join("-"))
+    }
+
+    /// Converts an ISBN-10 into the equivalent ISBN-13 (978 prefix)
+    ///
+    /// Hyphens and spaces in the input are ignored, the check digit of the input is validated.
+    /// Returns the ISBN-13 without hyphens.
+    pub fn isbn13_from_isbn10(isbn10: &str) -> Result<String, IdentifierError>{
+        let isbn10 = parse_isbn10(isbn10)?;
+        let mut digits = vec![9, 7, 8];
+        digits.extend_from_slice(&isbn10[..9]);
+        digits.push(isbn13_check_digit(&digits));
+
+        Ok(digits.iter().map(|digit| char::from(b'0' + *digit)).collect())
+    }
+
+    /// Converts an ISBN-13 into the equivalent ISBN-10
+    ///
+    /// Hyphens and spaces in the input are ignored, the check digit of the input is validated.
+    /// Only ISBN-13s with the 978 prefix have an ISBN-10 equivalent, for all others [IdentifierError::NoIsbn10Equivalent] is returned.
+    /// Returns the ISBN-10 without hyphens.
+    pub fn isbn10_from_isbn13(isbn13: &str) -> Result<String, IdentifierError>{
+        let isbn13_digits = parse_isbn13(isbn13)?;
+        if isbn13_digits[..3] != [9, 7, 8]{
+            return Err(IdentifierError::NoIsbn10Equivalent(isbn13.to_string()))
+        }
+        let mut digits = isbn13_digits[3..12].to_vec();
+        digits.push(isbn10_check_digit(&digits));
+
+        Ok(digits.iter().map(|digit| if *digit == 10 { 'X' } else { char::from(b'0' + *digit) }).collect())
+    }
+}
+
+/// Parses an ISBN-10 into its digits (X as 10) and validates the check digit
+fn parse_isbn10(value: &str) -> Result<Vec<u8>, IdentifierError>{
+    let chars : Vec<char> = value.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect();
+    if chars.len() != 10{
+        return Err(IdentifierError::InvalidIsbn(value.to_string()))
+    }
+
+    let mut digits = Vec::with_capacity(10);
+    for (i, c) in chars.iter().enumerate(){
+        match c.to_digit(10){
+            Some(digit) => digits.push(digit as u8),
+            None if i == 9 && (*c == 'X' || *c == 'x') => digits.push(10),
+            None => return Err(IdentifierError::InvalidIsbn(value.to_string()))
+        }
+    }
+
+    if isbn10_check_digit(&digits[..9]) != digits[9]{
+        return Err(IdentifierError::InvalidChecksum(value.to_string()))
+    }
+    Ok(digits)
+}
+
+/// Parses an ISBN-13 into its digits and validates the check digit
+fn parse_isbn13(value: &str) -> Result<Vec<u8>, IdentifierError>{
+    let digits : Option<Vec<u8>> = value.chars().filter(|c| *c != '-' && !c.is_whitespace()).map(|c| c.to_digit(10).map(|digit| digit as u8)).collect();
+    let digits = match digits{
+        Some(digits) if digits.len() == 13 => digits,
+        _ => return Err(IdentifierError::InvalidIsbn(value.to_string()))
+    };
+
+    if isbn13_check_digit(&digits[..12]) != digits[12]{
+        return Err(IdentifierError::InvalidChecksum(value.to_string()))
+    }
+    Ok(digits)
+}
+
+/// Validates an ISSN (NNNN-NNNC) including its check digit, C may be X
+fn validate_issn(value: &str) -> Result<(), IdentifierError>{
+    let chars : Vec<char> = value.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect();
+    if chars.len() != 8{
+        return Err(IdentifierError::InvalidFormat(IdentifierType::ISSN, value.to_string()))
+    }
+
+    let mut digits = Vec::with_capacity(8);
+    for (i, c) in chars.iter().enumerate(){
+        match c.to_digit(10){
+            Some(digit) => digits.push(digit),
+            None if i == 7 && (*c == 'X' || *c == 'x') => digits.push(10),
+            None => return Err(IdentifierError::InvalidFormat(IdentifierType::ISSN, value.to_string()))
+        }
+    }
+
+    let sum : u32 = digits[..7].iter().enumerate().map(|(i, digit)| (8 - i as u32) * digit).sum();
+    if (11 - sum % 11) % 11 != digits[7]{
+        return Err(IdentifierError::InvalidChecksum(value.to_string()))
+    }
+    Ok(())
+}
+
+/// Checks for a DOI of the form 10.NNNN/suffix, optionally prefixed by "doi:" or the doi.org resolver
+fn is_valid_doi(value: &str) -> bool{
+    match normalize_doi(value).split_once('/'){
+        Some((prefix, suffix)) => {
+            let registrant = prefix.strip_prefix("10.").unwrap_or("");
+            !registrant.is_empty() && registrant.chars().all(|c| c.is_ascii_digit() || c == '.') && !suffix.is_empty() && !suffix.contains(char::is_whitespace)
+        },
+        None => false
+    }
+}
+
+/// Checks for an absolute http(s) URL with a host and without whitespace
+fn is_valid_url(value: &str) -> bool{
+    let rest = strip_prefix_ignore_case(value, &["https://", "http://"]);
+
+    match rest{
+        Some(rest) => {
+            let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+            !host.is_empty() && !value.contains(char::is_whitespace)
+        },
+        None => false
+    }
+}
+
+/// Prefixes of DOIs pasted as URL or with a label, longest first
+const DOI_PREFIXES: [&str; 8] = ["https://dx.doi.org/", "http://dx.doi.org/", "https://doi.org/", "http://doi.org/", "dx.doi.org/", "doi.org/", "doi:", "doi "];
+
+/// Strips the first of the prefixes the value starts with (ignoring ascii case), None if it starts with none of them
+fn strip_prefix_ignore_case<'a>(value: &'a str, prefixes: &[&str]) -> Option<&'a str>{
+    prefixes.iter().find_map(|prefix| value.get(..prefix.len()).filter(|start| start.eq_ignore_ascii_case(prefix)).map(|_| &value[prefix.len()..]))
+}
+
+/// Reduces a DOI to the bare 10.NNNN/suffix form, e.g. "https://doi.org/10.1234/foo/" to "10.1234/foo"
+fn normalize_doi(value: &str) -> &str{
+    let value = value.trim();
+    let doi = strip_prefix_ignore_case(value, &DOI_PREFIXES).unwrap_or(value);
+    doi.trim().trim_end_matches('/')
+}
+
+/// Normalizes a URL: adds https:// if the scheme is missing, lowercases scheme and host and removes the slash of an empty path,
+/// e.g. "HTTPS://Example.org/" to "https://example.org". Empty (or whitespace-only) values are returned unchanged.
+fn normalize_url(value: &str) -> String{
+    if value.trim().is_empty(){
+        return value.to_string()
+    }
+    let value = value.trim();
+    let (scheme, rest) = match value.split_once("://"){
+        Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+        None => ("https".to_string(), value),
+    };
+
+    let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (host, path) = rest.split_at(host_end);
+    let path = if path == "/" { "" } else { path };
+
+    format!("{}://{}{}", scheme, host.to_ascii_lowercase(), path)
+}
+
+/// Checks for a URN of the form urn:NID:NSS
+fn is_valid_urn(value: &str) -> bool{
+    let mut parts = value.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()){
+        (Some(scheme), Some(nid), Some(nss)) => scheme.eq_ignore_ascii_case("urn") && !nid.is_empty() && nid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') && !nss.is_empty() && !value.contains(char::is_whitespace),
+        _ => false
+    }
+}
+
+/// Calculates the ISBN-10 check digit of the first 9 digits, 10 represents X
+fn isbn10_check_digit(digits: &[u8]) -> u8{
+    let sum : u32 = digits.iter().enumerate().map(|(i, digit)| (10 - i as u32) * *digit as u32).sum();
+    ((11 - sum % 11) % 11) as u8
+}
+
+/// Calculates the ISBN-13 check digit of the first 12 digits
+fn isbn13_check_digit(digits: &[u8]) -> u8{
+    let sum : u32 = digits.iter().enumerate().map(|(i, digit)| if i % 2 == 0 { *digit as u32 } else { 3 * *digit as u32 }).sum();
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// Errors when validating or converting an [Identifier]
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdentifierError{
+    /// Value is not a well-formed ISBN-10 / ISBN-13
+    InvalidIsbn(String),
+    /// Check digit of the value doesn't match
+    InvalidChecksum(String),
+    /// ISBN-13 doesn't have the 978 prefix, so there is no ISBN-10 for it
+    NoIsbn10Equivalent(String),
+    /// Value is not well-formed for the identifier type
+    InvalidFormat(IdentifierType, String),
+}
+
+impl Display for IdentifierError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IdentifierError::InvalidIsbn(value) => write!(f, "{} is not a valid ISBN.", value),
+            IdentifierError::InvalidChecksum(value) => write!(f, "Check digit of {} is wrong.", value),
+            IdentifierError::NoIsbn10Equivalent(value) => write!(f, "{} has no ISBN-10 equivalent, only ISBN-13s starting with 978 can be converted.", value),
+            IdentifierError::InvalidFormat(identifier_type, value) => write!(f, "{} is not a valid {}.", value, identifier_type.type_name()),
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
+impl std::error::Error for IdentifierError {}
+
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub enum IdentifierType{
     DOI,
     ISBN,
@@ -125,103 +656,1658 @@ pub enum IdentifierType{
     Other(String),
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
+impl IdentifierType{
+    /// Name of the identifier type, used as default name of new identifiers
+    fn type_name(&self) -> &str{
+        match self{
+            IdentifierType::DOI => "DOI",
+            IdentifierType::ISBN => "ISBN",
+            IdentifierType::ISSN => "ISSN",
+            IdentifierType::URL => "URL",
+            IdentifierType::URN => "URN",
+            IdentifierType::ORCID => "ORCID",
+            IdentifierType::ROR => "ROR",
+            IdentifierType::GND => "GND",
+            IdentifierType::Other(other) => other,
+        }
+    }
+}
+
+/// All data of a project needed for rendering
+/// Implements [Eq] and [Hash] (as do all Prepared* types), so projects can be compared or used as keys for caching rendered outputs.
+/// All ids are compared as well, e.g. two identifiers created by [Identifier::new] with the same value differ in their random id.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct PreparedProject{
     pub metadata: PreparedMetadata,
-    pub settings: Option<ProjectSettingsV4>,
+    /// Peers older than protocol version 6 exchange the settings as [ProjectSettingsV5], without the font options
+    pub settings: Option<ProjectSettingsV6>,
     pub sections: Vec<PreparedSection>,
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
-pub struct PreparedMetadata{
-    /// Book Title
-    pub title: String,
-    /// Subtitle of the book
-    pub subtitle: Option<String>,
-    /// List of authors of the book
-    pub authors: Vec<Person>,
-    /// List of editors
-    pub editors: Vec<Person>,
-    /// URL to a web version of the book or reference
-    pub web_url: Option<String>,
-    /// List of identifiers of the book (e.g. ISBNs)
-    pub identifiers: Option<Vec<Identifier>>,
-    /// Date of publication
-    pub published: Option<String>,
-    /// Languages of the book
-    pub languages: Option<Vec<Language>>,
-    /// Number of pages of the book (should be automatically calculated)
-    pub number_of_pages: Option<u32>,
-    /// Short abstract of the book
-    pub short_abstract: Option<String>,
-    /// Long abstract of the book
-    pub long_abstract: Option<String>,
-    /// Keywords of the book
-    pub keywords: Option<Vec<Keyword>>,
-    /// Dewey Decimal Classification (DDC) classes (subject groups)
-    pub ddc: Option<String>,
-    /// License of the book
-    pub license: Option<PreparedLicense>,
-    /// Series the book belongs to
-    pub series: Option<String>,
-    /// Volume of the book in the series
-    pub volume: Option<String>,
-    /// Edition of the book
-    pub edition: Option<String>,
-    /// Publisher of the book
-    pub publisher: Option<String>,
+impl Encode for PreparedProject{
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+        self.metadata.encode(encoder)?;
+        if crate::wire_version() < 6 {
+            self.settings.as_ref().map(|settings| ProjectSettingsV5{
+                toc_enabled: settings.toc_enabled,
+                csl_style: settings.csl_style.clone(),
+                csl_language_code: settings.csl_language_code.clone(),
+                metadata_page_additional_html: settings.metadata_page_additional_html.clone(),
+                cover_image_path: settings.cover_image_path.clone(),
+                backcover_image_path: settings.backcover_image_path.clone(),
+                running_headers: settings.running_headers.clone(),
+            }).encode(encoder)?;
+        } else {
+            self.settings.encode(encoder)?;
+        }
+        self.sections.encode(encoder)
+    }
 }
 
-/// Represents a Keyword, optionally with a GND ID
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
-pub struct Keyword{
-    pub title: String,
-    pub gnd: Option<Identifier>,
-}
+impl<Context> Decode<Context> for PreparedProject{
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+        let metadata = Decode::decode(decoder)?;
+        let settings = if crate::wire_version() < 6 {
+            Option::<ProjectSettingsV5>::decode(decoder)?.map(ProjectSettingsV6::from)
+        } else {
+            Decode::decode(decoder)?
+        };
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
-pub struct PreparedLicense{
-    CC0: bool,
-    CC_BY_4: bool,
-    CC_BY_SA_4: bool,
-    CC_BY_ND_4: bool,
-    CC_BY_NC_4: bool,
-    CC_BY_NC_SA_4: bool,
-    CC_BY_NC_ND_4: bool,
-    other: String,
+        Ok(PreparedProject{
+            metadata,
+            settings,
+            sections: Decode::decode(decoder)?,
+        })
+    }
 }
 
-/// Holds all different (CC) licenses or a custom license
-#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq)]
-pub enum License{
-    CC0,
-    CC_BY_4,
-    CC_BY_SA_4,
-    CC_BY_ND_4,
-    CC_BY_NC_4,
-    CC_BY_NC_SA_4,
-    CC_BY_NC_ND_4,
-    Other(String),
-}
+bincode::impl_borrow_decode!(PreparedProject);
 
-/// implement from License -> PreparedLicense
-impl From<License> for PreparedLicense{
-    fn from(license: License) -> Self{
-        match license{
-            License::CC0 => PreparedLicense{CC0: true, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
-            License::CC_BY_4 => PreparedLicense{CC0: false, CC_BY_4: true, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
-            License::CC_BY_SA_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: true, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
-            License::CC_BY_ND_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: true, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
-            License::CC_BY_NC_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: true, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
-            License::CC_BY_NC_SA_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: true, CC_BY_NC_ND_4: false, other: String::new()},
-            License::CC_BY_NC_ND_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: true, other: String::new()},
-            License::Other(other) => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other},
-        }
+impl PreparedProject{
+    /// Builds the table of contents from the sections
+    ///
+    /// Top-level sections get level 1, their sub sections level 2 and so on. The toc_title is used if present, the title otherwise.
+    /// Sections which aren't visible in the toc are left out together with their sub sections.
+    pub fn build_toc(&self) -> Vec<TocEntry>{
+        build_toc_entries(&self.sections, 1)
     }
-}
+
+    /// Checks the project for problems which would make rendering fail or produce a broken book
+    ///
+    /// Checks for an empty title, a project without sections, sections with an empty title, identifiers which aren't well-formed
+    /// (of the book, the sections and all authors and editors, see [Identifier::validate]) and duplicated section or endnote ids.
+    /// Returns all issues found, not just the first one.
+    pub fn validate(&self) -> Result<(), Vec<ValidationIssue>>{
+        let mut issues = Vec::new();
+
+        if self.metadata.title.trim().is_empty(){
+            issues.push(ValidationIssue::EmptyTitle);
+        }
+        if self.sections.is_empty(){
+            issues.push(ValidationIssue::NoSections);
+        }
+
+        let persons = self.metadata.authors.iter().chain(self.metadata.editors.iter());
+        validate_identifiers(None, self.metadata.identifiers.iter().flatten(), persons, &mut issues);
+        for section in &self.sections{
+            section.validate(&mut issues);
+        }
+        if let Err(duplicates) = self.check_id_uniqueness(){
+            issues.extend(duplicates.into_iter().map(ValidationIssue::DuplicateId));
+        }
+
+        if issues.is_empty(){
+            Ok(())
+        }else{
+            Err(issues)
+        }
+    }
+
+    /// Checks that no id is used by more than one section or endnote, including sub sections
+    /// Returns each duplicated id once, in the order of their second occurrence (see [PreparedProject::sections_iter]).
+    pub fn check_id_uniqueness(&self) -> Result<(), Vec<uuid::Uuid>>{
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicates = Vec::new();
+
+        let ids = self.sections_iter().flat_map(|section| std::iter::once(section.id).chain(section.endnotes.iter().map(|endnote| endnote.id)));
+        for id in ids{
+            if !seen.insert(id) && !duplicates.contains(&id){
+                duplicates.push(id);
+            }
+        }
+
+        if duplicates.is_empty(){
+            Ok(())
+        }else{
+            Err(duplicates)
+        }
+    }
+
+    /// Collects all authors and editors of the book and of all sections (including sub sections), each person only once
+    ///
+    /// Entries are merged with [Person::merge] if [Person::is_same_person] matches, so the first occurrence takes precedence:
+    /// book authors, then book editors, then the sections in document order.
+    pub fn unique_contributors(&self) -> Vec<Person>{
+        let mut persons : Vec<&Person> = self.metadata.authors.iter().chain(self.metadata.editors.iter()).collect();
+        for section in &self.sections{
+            section.collect_contributors(&mut persons);
+        }
+
+        let mut unique : Vec<Person> = Vec::new();
+        for person in persons{
+            match unique.iter_mut().find(|existing| existing.is_same_person(person)){
+                Some(existing) => existing.merge(person.clone()),
+                None => unique.push(person.clone()),
+            }
+        }
+        unique
+    }
+
+    /// Iterates over all sections in document order, each section followed by its sub sections (depth-first)
+    pub fn sections_iter(&self) -> impl Iterator<Item = &PreparedSection>{
+        SectionsIter{
+            stack: vec![self.sections.iter()],
+        }
+    }
+
+    /// Iterates over the content blocks of all sections in document order, see [PreparedProject::sections_iter]
+    /// The blocks of a section come before the blocks of its sub sections.
+    pub fn content_blocks(&self) -> impl Iterator<Item = &PreparedContentBlock>{
+        self.sections_iter().flat_map(|section| section.children.iter())
+    }
+
+    /// Mutable variant of [PreparedProject::content_blocks]
+    pub fn content_blocks_mut(&mut self) -> impl Iterator<Item = &mut PreparedContentBlock>{
+        let mut blocks = Vec::new();
+        collect_content_blocks_mut(&mut self.sections, &mut blocks);
+        blocks.into_iter()
+    }
+
+    /// Number of words in the content blocks and endnotes of all sections, see [PreparedSection::word_count]
+    pub fn word_count(&self) -> usize{
+        self.sections_iter().map(PreparedSection::word_count).sum()
+    }
+
+    /// Number of characters (including spaces) in the content blocks and endnotes of all sections, see [PreparedSection::char_count]
+    pub fn char_count(&self) -> usize{
+        self.sections_iter().map(PreparedSection::char_count).sum()
+    }
+
+    /// Number of words of each section (without its sub sections) in document order, see [PreparedProject::sections_iter]
+    pub fn word_counts_per_section(&self) -> Vec<(uuid::Uuid, usize)>{
+        self.sections_iter().map(|section| (section.id, section.word_count())).collect()
+    }
+
+    /// Replaces every occurrence of `find` in the html of the content blocks, e.g. to correct a misspelled name across the whole book
+    ///
+    /// Only blocks of one of the `block_types` are changed, an empty slice changes blocks of all types.
+    /// The html is searched as is, so markup (e.g. link targets) is replaced as well. Returns the number of replacements.
+    pub fn replace_in_blocks(&mut self, find: &str, replace: &str, block_types: &[BlockType]) -> usize{
+        if find.is_empty(){
+            return 0
+        }
+
+        let mut replacements = 0;
+        for block in self.content_blocks_mut().filter(|block| block_types.is_empty() || block_types.contains(&block.block_type)){
+            let count = block.html.matches(find).count();
+            if count > 0{
+                block.html = block.html.replace(find, replace);
+                replacements += count;
+            }
+        }
+
+        replacements
+    }
+
+    /// Same as [PreparedProject::replace_in_blocks], but with a regular expression
+    /// `replace` may reference capture groups, e.g. "$1", see [regex::Regex::replace_all].
+    #[cfg(feature = "regex")]
+    pub fn replace_regex_in_blocks(&mut self, pattern: &regex::Regex, replace: &str, block_types: &[BlockType]) -> usize{
+        let mut replacements = 0;
+        for block in self.content_blocks_mut().filter(|block| block_types.is_empty() || block_types.contains(&block.block_type)){
+            let count = pattern.find_iter(&block.html).count();
+            if count > 0{
+                block.html = pattern.replace_all(&block.html, replace).into_owned();
+                replacements += count;
+            }
+        }
+
+        replacements
+    }
+
+    /// Renumbers the endnotes of all sections in document order (see [PreparedProject::sections_iter])
+    ///
+    /// Within a section the endnotes keep their order. The references in the block html aren't changed,
+    /// so the returned map (section id -> old number -> new number) should be used to rewrite them.
+    pub fn renumber_endnotes(&mut self, mode: EndnoteNumbering) -> HashMap<uuid::Uuid, HashMap<usize, usize>>{
+        let mut mapping = HashMap::new();
+        let mut next = 1;
+        renumber_section_endnotes(&mut self.sections, mode, &mut next, &mut mapping);
+        mapping
+    }
+
+    /// Counts how many content blocks of each [BlockType] the project contains, including all sub sections
+    pub fn block_type_counts(&self) -> HashMap<BlockType, usize>{
+        let mut counts = HashMap::new();
+        for section in &self.sections{
+            section.count_block_types(&mut counts);
+        }
+        counts
+    }
+
+    /// Checks whether the language of the title, subtitle, abstracts and keywords matches one of the declared book languages
+    ///
+    /// The language is detected with a simple stop word heuristic, texts which can't be attributed to a language are ignored.
+    /// Returns nothing if the book doesn't declare any languages.
+    pub fn check_language_consistency(&self) -> Vec<LanguageWarning>{
+        let metadata = &self.metadata;
+        let declared = match &metadata.languages{
+            Some(languages) if !languages.is_empty() => languages,
+            _ => return Vec::new()
+        };
+
+        let keywords = metadata.keywords.as_ref().map(|keywords| keywords.iter().map(|keyword| keyword.title.as_str()).collect::<Vec<&str>>().join(" "));
+        let texts = [
+            ("title", Some(&metadata.title)),
+            ("subtitle", metadata.subtitle.as_ref()),
+            ("short_abstract", metadata.short_abstract.as_ref()),
+            ("long_abstract", metadata.long_abstract.as_ref()),
+            ("keywords", keywords.as_ref()),
+        ];
+
+        texts.into_iter().filter_map(|(field, text)| {
+            let detected = detect_language(text?)?;
+            if declared.contains(&detected){
+                None
+            }else{
+                Some(LanguageWarning{
+                    field: field.to_string(),
+                    detected,
+                })
+            }
+        }).collect()
+    }
+
+    /// Moves a section (including its sub sections and endnotes) to a new position
+    ///
+    /// Arguments
+    /// * `section_id` - id of the section to move
+    /// * `new_parent_id` - id of the section to move it into, None to move it to the top level
+    /// * `index` - position in the sub sections of the new parent, counted after the section was removed from its old position
+    ///
+    /// The project is left unchanged if an error is returned.
+    pub fn move_section(&mut self, section_id: uuid::Uuid, new_parent_id: Option<uuid::Uuid>, index: usize) -> Result<(), MoveError>{
+        let (old_parent_id, old_index) = locate_section(&self.sections, section_id, None).ok_or(MoveError::SectionNotFound(section_id))?;
+
+        let mut target_len = match new_parent_id{
+            Some(parent_id) => {
+                let section = find_section(&self.sections, section_id).ok_or(MoveError::SectionNotFound(section_id))?;
+                if parent_id == section_id || find_section(&section.sub_sections, parent_id).is_some(){
+                    return Err(MoveError::TargetIsDescendant(parent_id))
+                }
+                find_section(&self.sections, parent_id).ok_or(MoveError::ParentNotFound(parent_id))?.sub_sections.len()
+            },
+            None => self.sections.len()
+        };
+        if old_parent_id == new_parent_id{
+            target_len -= 1;
+        }
+        if index > target_len{
+            return Err(MoveError::IndexOutOfBounds{index, len: target_len})
+        }
+
+        let section = self.sub_sections_mut(old_parent_id).remove(old_index);
+        self.sub_sections_mut(new_parent_id).insert(index, section);
+        Ok(())
+    }
+
+    /// Returns the sub sections of the section with the given id or the top level sections for None.
+    /// Panics if the section doesn't exist.
+    fn sub_sections_mut(&mut self, parent_id: Option<uuid::Uuid>) -> &mut Vec<PreparedSection>{
+        match parent_id{
+            Some(parent_id) => &mut find_section_mut(&mut self.sections, parent_id).expect("Parent section has to exist").sub_sections,
+            None => &mut self.sections
+        }
+    }
+}
+
+/// Searches the section tree for a section with the given id
+fn find_section(sections: &[PreparedSection], id: uuid::Uuid) -> Option<&PreparedSection>{
+    for section in sections{
+        if section.id == id{
+            return Some(section)
+        }
+        if let Some(found) = find_section(&section.sub_sections, id){
+            return Some(found)
+        }
+    }
+    None
+}
+
+fn find_section_mut(sections: &mut [PreparedSection], id: uuid::Uuid) -> Option<&mut PreparedSection>{
+    for section in sections{
+        if section.id == id{
+            return Some(section)
+        }
+        if let Some(found) = find_section_mut(&mut section.sub_sections, id){
+            return Some(found)
+        }
+    }
+    None
+}
+
+/// Returns the id of the parent section (None for top level sections) and the index of the section in its parent
+fn locate_section(sections: &[PreparedSection], id: uuid::Uuid, parent_id: Option<uuid::Uuid>) -> Option<(Option<uuid::Uuid>, usize)>{
+    for (i, section) in sections.iter().enumerate(){
+        if section.id == id{
+            return Some((parent_id, i))
+        }
+        if let Some(found) = locate_section(&section.sub_sections, id, Some(section.id)){
+            return Some(found)
+        }
+    }
+    None
+}
+
+/// Errors when moving a section with [PreparedProject::move_section]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoveError{
+    /// There is no section with the id to move
+    SectionNotFound(uuid::Uuid),
+    /// There is no section with the id of the new parent
+    ParentNotFound(uuid::Uuid),
+    /// New parent is the moved section itself or one of its sub sections
+    TargetIsDescendant(uuid::Uuid),
+    /// Index is larger than the number of sub sections of the new parent
+    IndexOutOfBounds{index: usize, len: usize},
+}
+
+impl Display for MoveError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::SectionNotFound(id) => write!(f, "Couldn't find section {}.", id),
+            MoveError::ParentNotFound(id) => write!(f, "Couldn't find parent section {}.", id),
+            MoveError::TargetIsDescendant(id) => write!(f, "Can't move a section into itself or one of its sub sections ({}).", id),
+            MoveError::IndexOutOfBounds{index, len} => write!(f, "Index {} is out of bounds, parent has {} sub sections.", index, len),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// Problem found by [PreparedProject::validate]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue{
+    /// The book has no title
+    EmptyTitle,
+    /// The book has no sections
+    NoSections,
+    /// The section with this id has no title
+    EmptySectionTitle(uuid::Uuid),
+    /// An identifier of the book (section None), of a section or of one of their authors and editors isn't well-formed
+    InvalidIdentifier{
+        section: Option<uuid::Uuid>,
+        error: IdentifierError,
+    },
+    /// More than one section or endnote has this id, see [PreparedProject::check_id_uniqueness]
+    DuplicateId(uuid::Uuid),
+    /// The export format isn't defined by the template, see [crate::RenderingRequest::check_export_formats]
+    UnknownExportFormat(String),
+    /// The export steps of the export format with this slug don't form a working pipeline, see [crate::export_formats::ExportFormat::validate]
+    InvalidExportFormat{
+        format: String,
+        issue: ExportFormatIssue,
+    },
+}
+
+impl Display for ValidationIssue{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationIssue::EmptyTitle => write!(f, "The book has no title."),
+            ValidationIssue::NoSections => write!(f, "The book has no sections."),
+            ValidationIssue::EmptySectionTitle(id) => write!(f, "Section {} has no title.", id),
+            ValidationIssue::InvalidIdentifier{section: None, error} => write!(f, "Invalid identifier in the book metadata: {}", error),
+            ValidationIssue::InvalidIdentifier{section: Some(id), error} => write!(f, "Invalid identifier in section {}: {}", id, error),
+            ValidationIssue::DuplicateId(id) => write!(f, "The id {} is used more than once.", id),
+            ValidationIssue::UnknownExportFormat(name) => write!(f, "The template has no export format {}.", name),
+            ValidationIssue::InvalidExportFormat{format, issue} => write!(f, "Export format {}: {}", format, issue),
+        }
+    }
+}
+
+/// Validates the identifiers and the identifiers of the persons, adds an issue for each invalid one
+fn validate_identifiers<'a>(section: Option<uuid::Uuid>, identifiers: impl Iterator<Item = &'a Identifier>, persons: impl Iterator<Item = &'a Person>, issues: &mut Vec<ValidationIssue>){
+    let person_identifiers = persons.flat_map(|person| [&person.orcid, &person.gnd, &person.ror].into_iter().flatten());
+    for identifier in identifiers.chain(person_identifiers){
+        if let Err(error) = identifier.validate(){
+            issues.push(ValidationIssue::InvalidIdentifier{section, error});
+        }
+    }
+}
+
+/// Metadata text whose detected language isn't one of the declared book languages
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageWarning{
+    /// Name of the [PreparedMetadata] field, e.g. short_abstract
+    pub field: String,
+    /// Language the text seems to be written in
+    pub detected: Language,
+}
+
+const GERMAN_STOP_WORDS: &[&str] = &["der", "die", "das", "und", "ist", "nicht", "mit", "von", "den", "dem", "des", "ein", "eine", "einer", "zu", "auf", "für", "sich", "auch", "werden", "wird", "im", "zum", "zur", "über", "oder", "wie", "bei", "sind", "dass"];
+const ENGLISH_STOP_WORDS: &[&str] = &["the", "and", "of", "to", "is", "that", "for", "with", "this", "on", "are", "by", "be", "from", "which", "it", "its", "we", "how", "their", "not", "or", "has", "have", "these"];
+
+/// Guesses the language of a text by counting German and English stop words (and umlauts)
+/// Returns None if there isn't enough evidence for either language.
+fn detect_language(text: &str) -> Option<Language>{
+    let mut german = 0;
+    let mut english = 0;
+    for word in text.split(|c: char| !c.is_alphabetic()).filter(|word| !word.is_empty()){
+        let word = word.to_lowercase();
+        if GERMAN_STOP_WORDS.contains(&word.as_str()) || word.contains(['ä', 'ö', 'ü', 'ß']){
+            german += 1;
+        }
+        if ENGLISH_STOP_WORDS.contains(&word.as_str()){
+            english += 1;
+        }
+    }
+
+    if german >= 2 && german >= 2 * english{
+        Some(Language::DE)
+    }else if english >= 2 && english >= 2 * german{
+        Some(Language::EN)
+    }else{
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct PreparedMetadata{
+    /// Book Title
+    pub title: String,
+    /// Subtitle of the book
+    pub subtitle: Option<String>,
+    /// List of authors of the book
+    pub authors: Vec<Person>,
+    /// List of editors
+    pub editors: Vec<Person>,
+    /// URL to a web version of the book or reference
+    pub web_url: Option<String>,
+    /// List of identifiers of the book (e.g. ISBNs)
+    pub identifiers: Option<Vec<Identifier>>,
+    /// Date of publication
+    pub published: Option<String>,
+    /// Languages of the book
+    pub languages: Option<Vec<Language>>,
+    /// Number of pages of the book, set from the page count of a rendered PDF with [PreparedMetadata::set_page_count]
+    pub number_of_pages: Option<u32>,
+    /// Short abstract of the book
+    pub short_abstract: Option<String>,
+    /// Long abstract of the book
+    pub long_abstract: Option<String>,
+    /// Keywords of the book
+    pub keywords: Option<Vec<Keyword>>,
+    /// Dewey Decimal Classification (DDC) classes (subject groups)
+    pub ddc: Option<String>,
+    /// License of the book
+    pub license: Option<PreparedLicense>,
+    /// Series the book belongs to
+    pub series: Option<String>,
+    /// Volume of the book in the series
+    pub volume: Option<String>,
+    /// Edition of the book
+    pub edition: Option<String>,
+    /// Publisher of the book
+    pub publisher: Option<String>,
+    /// Title (and subtitle) of the book in other languages than the main title.
+    /// Only sent to peers with protocol version 23 or newer, metadata of older peers is decoded without translations.
+    #[serde(default)]
+    pub title_translations: Vec<TitleTranslation>,
+}
+
+impl Encode for PreparedMetadata{
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+        self.title.encode(encoder)?;
+        self.subtitle.encode(encoder)?;
+        self.authors.encode(encoder)?;
+        self.editors.encode(encoder)?;
+        self.web_url.encode(encoder)?;
+        self.identifiers.encode(encoder)?;
+        self.published.encode(encoder)?;
+        self.languages.encode(encoder)?;
+        self.number_of_pages.encode(encoder)?;
+        self.short_abstract.encode(encoder)?;
+        self.long_abstract.encode(encoder)?;
+        self.keywords.encode(encoder)?;
+        self.ddc.encode(encoder)?;
+        self.license.encode(encoder)?;
+        self.series.encode(encoder)?;
+        self.volume.encode(encoder)?;
+        self.edition.encode(encoder)?;
+        self.publisher.encode(encoder)?;
+        if crate::wire_version() >= 23 {
+            self.title_translations.encode(encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Context> Decode<Context> for PreparedMetadata{
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+        Ok(PreparedMetadata{
+            title: Decode::decode(decoder)?,
+            subtitle: Decode::decode(decoder)?,
+            authors: Decode::decode(decoder)?,
+            editors: Decode::decode(decoder)?,
+            web_url: Decode::decode(decoder)?,
+            identifiers: Decode::decode(decoder)?,
+            published: Decode::decode(decoder)?,
+            languages: Decode::decode(decoder)?,
+            number_of_pages: Decode::decode(decoder)?,
+            short_abstract: Decode::decode(decoder)?,
+            long_abstract: Decode::decode(decoder)?,
+            keywords: Decode::decode(decoder)?,
+            ddc: Decode::decode(decoder)?,
+            license: Decode::decode(decoder)?,
+            series: Decode::decode(decoder)?,
+            volume: Decode::decode(decoder)?,
+            edition: Decode::decode(decoder)?,
+            publisher: Decode::decode(decoder)?,
+            title_translations: if crate::wire_version() >= 23 { Decode::decode(decoder)? } else { Vec::new() },
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(PreparedMetadata);
+
+/// Title of the book in another language, see [PreparedMetadata::title_translations]
+#[derive(Serialize, Deserialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub struct TitleTranslation{
+    pub language: Language,
+    pub title: String,
+    pub subtitle: Option<String>,
+}
+
+#[cfg(feature = "crossref")]
+impl PreparedMetadata{
+    /// Serializes the metadata into a Crossref `<book>` element, to be embedded into the body of a Crossref deposit (`<doi_batch>`).
+    ///
+    /// Authors and editors are added as contributors (with their ORCID if available), the first [IdentifierType::DOI] identifier is used as DOI
+    /// (only together with the [PreparedMetadata::web_url] it resolves to, Crossref rejects `doi_data` without resource)
+    /// and all [IdentifierType::ISBN] identifiers are added as isbn. [PreparedMetadata::title_translations] are added as original_language_title.
+    /// The first declared language of the book is set as language of the metadata.
+    pub fn to_crossref_xml(&self) -> String{
+        use quick_xml::events::BytesText;
+        use quick_xml::Writer;
+
+        let book_type = if self.authors.is_empty() && !self.editors.is_empty() { "edited_book" } else { "monograph" };
+        let language = self.languages.as_ref().and_then(|languages| languages.first()).map(Language::to_iso_code);
+        let doi = self.identifiers_of_type(&IdentifierType::DOI).next();
+        let isbns : Vec<&Identifier> = self.identifiers_of_type(&IdentifierType::ISBN).collect();
+        let contributors : Vec<(&Person, &str)> = self.authors.iter().map(|author| (author, "author"))
+            .chain(self.editors.iter().map(|editor| (editor, "editor"))).collect();
+
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+        writer.create_element("book").with_attribute(("book_type", book_type)).write_inner_content(|writer| {
+            let mut book_metadata = writer.create_element("book_metadata");
+            if let Some(language) = language{
+                book_metadata = book_metadata.with_attribute(("language", language));
+            }
+            book_metadata.write_inner_content(|writer| {
+                if !contributors.is_empty(){
+                    writer.create_element("contributors").write_inner_content(|writer| {
+                        for (i, (person, role)) in contributors.iter().enumerate(){
+                            let sequence = if i == 0 { "first" } else { "additional" };
+                            writer.create_element("person_name")
+                                .with_attribute(("sequence", sequence))
+                                .with_attribute(("contributor_role", *role))
+                                .write_inner_content(|writer| {
+                                    if let Some(first_names) = &person.first_names{
+                                        writer.create_element("given_name").write_text_content(BytesText::new(first_names))?;
+                                    }
+                                    writer.create_element("surname").write_text_content(BytesText::new(&person.last_names))?;
+                                    if let Some(orcid) = &person.orcid{
+                                        let orcid = if orcid.value.starts_with("http") { orcid.value.clone() } else { format!("https://orcid.org/{}", orcid.value) };
+                                        writer.create_element("ORCID").write_text_content(BytesText::new(&orcid))?;
+                                    }
+                                    Ok(())
+                                })?;
+                        }
+                        Ok(())
+                    })?;
+                }
+
+                writer.create_element("titles").write_inner_content(|writer| {
+                    writer.create_element("title").write_text_content(BytesText::new(&self.title))?;
+                    if let Some(subtitle) = &self.subtitle{
+                        writer.create_element("subtitle").write_text_content(BytesText::new(subtitle))?;
+                    }
+                    for translation in &self.title_translations{
+                        writer.create_element("original_language_title")
+                            .with_attribute(("language", translation.language.to_iso_code()))
+                            .write_text_content(BytesText::new(&translation.title))?;
+                        if let Some(subtitle) = &translation.subtitle{
+                            writer.create_element("subtitle").write_text_content(BytesText::new(subtitle))?;
+                        }
+                    }
+                    Ok(())
+                })?;
+
+                if let Some(short_abstract) = &self.short_abstract{
+                    let mut jats_abstract = writer.create_element("jats:abstract");
+                    if let Some(language) = language{
+                        jats_abstract = jats_abstract.with_attribute(("xml:lang", language));
+                    }
+                    jats_abstract.write_inner_content(|writer| {
+                        writer.create_element("jats:p").write_text_content(BytesText::new(short_abstract))?;
+                        Ok(())
+                    })?;
+                }
+
+                if let Some(edition) = &self.edition{
+                    writer.create_element("edition_number").write_text_content(BytesText::new(edition))?;
+                }
+
+                if let Some((year, month, day)) = self.published.as_deref().and_then(split_date){
+                    writer.create_element("publication_date").write_inner_content(|writer| {
+                        if let Some(month) = month{
+                            writer.create_element("month").write_text_content(BytesText::new(&format!("{:02}", month)))?;
+                        }
+                        if let Some(day) = day{
+                            writer.create_element("day").write_text_content(BytesText::new(&format!("{:02}", day)))?;
+                        }
+                        writer.create_element("year").write_text_content(BytesText::new(&year.to_string()))?;
+                        Ok(())
+                    })?;
+                }
+
+                if isbns.is_empty(){
+                    writer.create_element("noisbn").with_attribute(("reason", "monograph")).write_empty()?;
+                }
+                for isbn in isbns{
+                    writer.create_element("isbn").write_text_content(BytesText::new(&isbn.value))?;
+                }
+
+                if let Some(publisher) = &self.publisher{
+                    writer.create_element("publisher").write_inner_content(|writer| {
+                        writer.create_element("publisher_name").write_text_content(BytesText::new(publisher))?;
+                        Ok(())
+                    })?;
+                }
+
+                // Crossref requires the resource the DOI resolves to
+                if let (Some(doi), Some(web_url)) = (doi, &self.web_url){
+                    writer.create_element("doi_data").write_inner_content(|writer| {
+                        writer.create_element("doi").write_text_content(BytesText::new(&doi.value))?;
+                        writer.create_element("resource").write_text_content(BytesText::new(web_url))?;
+                        Ok(())
+                    })?;
+                }
+                Ok(())
+            })?;
+            Ok(())
+        }).expect("Writing XML into a Vec can't fail");
+
+        String::from_utf8(writer.into_inner()).expect("XML writer produced invalid UTF-8")
+    }
+}
+
+#[cfg(feature = "dublin-core")]
+impl PreparedMetadata{
+    /// Serializes the metadata into a Dublin Core XML document (`<oai_dc:dc>` root, as used by OAI-PMH)
+    ///
+    /// Authors are added as dc:creator, editors as dc:contributor (both last name first). Each language, keyword and identifier
+    /// gets its own element, DOIs are written as https://doi.org/ link, ISBNs and ISSNs as URN.
+    pub fn to_dublin_core_xml(&self) -> String{
+        use quick_xml::events::{BytesDecl, BytesText, Event};
+        use quick_xml::Writer;
+
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+        writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None))).expect("Writing XML into a Vec can't fail");
+        writer.create_element("oai_dc:dc")
+            .with_attribute(("xmlns:oai_dc", "http://www.openarchives.org/OAI/2.0/oai_dc/"))
+            .with_attribute(("xmlns:dc", "http://purl.org/dc/elements/1.1/"))
+            .write_inner_content(|writer| {
+                let title = match &self.subtitle{
+                    Some(subtitle) => format!("{}: {}", self.title, subtitle),
+                    None => self.title.clone(),
+                };
+                writer.create_element("dc:title").write_text_content(BytesText::new(&title))?;
+
+                for author in self.authors.iter(){
+                    writer.create_element("dc:creator").write_text_content(BytesText::new(&author.display_name(NameOrder::LastFirst)))?;
+                }
+                for editor in self.editors.iter(){
+                    writer.create_element("dc:contributor").write_text_content(BytesText::new(&editor.display_name(NameOrder::LastFirst)))?;
+                }
+                for language in self.languages.iter().flatten(){
+                    writer.create_element("dc:language").write_text_content(BytesText::new(language.to_iso_code()))?;
+                }
+                if let Some(publisher) = &self.publisher{
+                    writer.create_element("dc:publisher").write_text_content(BytesText::new(publisher))?;
+                }
+                if let Some(published) = &self.published{
+                    let published = normalize_publication_date(published).unwrap_or_else(|| published.clone());
+                    writer.create_element("dc:date").write_text_content(BytesText::new(&published))?;
+                }
+                if let Some(license) = &self.license{
+                    writer.create_element("dc:rights").write_text_content(BytesText::new(&license.to_string()))?;
+                }
+                for keyword in self.keywords.iter().flatten(){
+                    writer.create_element("dc:subject").write_text_content(BytesText::new(&keyword.title))?;
+                }
+                for identifier in self.identifiers.iter().flatten(){
+                    let value = match identifier.identifier_type{
+                        IdentifierType::DOI => format!("https://doi.org/{}", identifier.value),
+                        IdentifierType::ISBN => format!("urn:isbn:{}", identifier.value),
+                        IdentifierType::ISSN => format!("urn:issn:{}", identifier.value),
+                        _ => identifier.value.clone(),
+                    };
+                    writer.create_element("dc:identifier").write_text_content(BytesText::new(&value))?;
+                }
+                Ok(())
+            }).expect("Writing XML into a Vec can't fail");
+
+        String::from_utf8(writer.into_inner()).expect("XML writer produced invalid UTF-8")
+    }
+}
+
+#[cfg(feature = "jats")]
+impl PreparedSectionMetadata{
+    /// Serializes the metadata of the section into a JATS `<article-meta>` element, treating the section as article
+    ///
+    /// Identifiers are written as `<article-id>` with their [IdentifierType] as pub-id-type (e.g. "doi" or "isbn", "uri" for URLs),
+    /// authors and editors as `<contrib>` with their ORCID as `<contrib-id>`. The particle is part of the surname, since JATS has no field for it.
+    /// The first language is set as xml:lang of the article title.
+    pub fn to_jats_xml(&self) -> String{
+        use quick_xml::events::BytesText;
+        use quick_xml::Writer;
+
+        let language = self.lang.languages().into_iter().next();
+        let contributors : Vec<(&Person, &str)> = self.authors.iter().map(|author| (author, "author"))
+            .chain(self.editors.iter().map(|editor| (editor, "editor"))).collect();
+
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+        writer.create_element("article-meta").write_inner_content(|writer| {
+            for identifier in self.identifiers.iter(){
+                writer.create_element("article-id")
+                    .with_attribute(("pub-id-type", jats_pub_id_type(&identifier.identifier_type)))
+                    .write_text_content(BytesText::new(&identifier.value))?;
+            }
+
+            writer.create_element("title-group").write_inner_content(|writer| {
+                let mut title = writer.create_element("article-title");
+                if let Some(language) = &language{
+                    title = title.with_attribute(("xml:lang", language.to_iso_code()));
+                }
+                title.write_text_content(BytesText::new(&self.title))?;
+                if let Some(subtitle) = &self.subtitle{
+                    writer.create_element("subtitle").write_text_content(BytesText::new(subtitle))?;
+                }
+                if let Some(toc_title) = &self.toc_title{
+                    writer.create_element("alt-title").with_attribute(("alt-title-type", "toc")).write_text_content(BytesText::new(toc_title))?;
+                }
+                Ok(())
+            })?;
+
+            if !contributors.is_empty(){
+                writer.create_element("contrib-group").write_inner_content(|writer| {
+                    for (person, role) in contributors.iter(){
+                        writer.create_element("contrib").with_attribute(("contrib-type", *role)).write_inner_content(|writer| {
+                            if let Some(orcid) = &person.orcid{
+                                let orcid = if orcid.value.starts_with("http") { orcid.value.clone() } else { format!("https://orcid.org/{}", orcid.value) };
+                                writer.create_element("contrib-id").with_attribute(("contrib-id-type", "orcid")).write_text_content(BytesText::new(&orcid))?;
+                            }
+                            writer.create_element("name").write_inner_content(|writer| {
+                                let surname = join_name_parts(&[person.particle.as_deref(), Some(person.last_names.as_str())], " ");
+                                writer.create_element("surname").write_text_content(BytesText::new(&surname))?;
+                                if let Some(first_names) = &person.first_names{
+                                    writer.create_element("given-names").write_text_content(BytesText::new(first_names))?;
+                                }
+                                if let Some(prefix) = &person.name_prefix{
+                                    writer.create_element("prefix").write_text_content(BytesText::new(prefix))?;
+                                }
+                                if let Some(suffix) = &person.name_suffix{
+                                    writer.create_element("suffix").write_text_content(BytesText::new(suffix))?;
+                                }
+                                Ok(())
+                            })?;
+                            Ok(())
+                        })?;
+                    }
+                    Ok(())
+                })?;
+            }
+
+            if let Some((year, month, day)) = self.published.as_deref().and_then(split_date){
+                writer.create_element("pub-date").with_attribute(("date-type", "pub")).write_inner_content(|writer| {
+                    if let Some(day) = day{
+                        writer.create_element("day").write_text_content(BytesText::new(&format!("{:02}", day)))?;
+                    }
+                    if let Some(month) = month{
+                        writer.create_element("month").write_text_content(BytesText::new(&format!("{:02}", month)))?;
+                    }
+                    writer.create_element("year").write_text_content(BytesText::new(&year.to_string()))?;
+                    Ok(())
+                })?;
+            }
+
+            if let Some(web_url) = &self.web_url{
+                writer.create_element("self-uri").with_attribute(("xlink:href", web_url.as_str())).write_empty()?;
+            }
+            Ok(())
+        }).expect("Writing XML into a Vec can't fail");
+
+        String::from_utf8(writer.into_inner()).expect("XML writer produced invalid UTF-8")
+    }
+
+    /// Reads the metadata back from a JATS `<article-meta>` element (e.g. written by [PreparedSectionMetadata::to_jats_xml])
+    ///
+    /// Unknown elements are ignored. Contributors without contrib-type are treated as authors, the particle stays part of the last names.
+    /// Identifiers get new ids and their default name, see [Identifier::new]. The published date is stored as YYYY-MM-DD (or YYYY-MM, YYYY).
+    pub fn from_jats_xml(xml: &str) -> Result<PreparedSectionMetadata, JatsError>{
+        use quick_xml::events::{BytesStart, Event};
+        use quick_xml::Reader;
+
+        fn attribute(element: &BytesStart, name: &str) -> Result<Option<String>, JatsError>{
+            match element.try_get_attribute(name).map_err(|e| JatsError::InvalidXml(e.to_string()))?{
+                Some(attribute) => Ok(Some(quick_xml::escape::unescape(&attribute.value).map_err(|e| JatsError::InvalidXml(e.to_string()))?.into_owned())),
+                None => Ok(None),
+            }
+        }
+
+        let mut reader = Reader::from_str(xml);
+        let mut found = false;
+        let mut title = None;
+        let mut metadata = PreparedSectionMetadata{
+            title: String::new(),
+            subtitle: None,
+            toc_title: None,
+            authors: Vec::new(),
+            editors: Vec::new(),
+            web_url: None,
+            identifiers: Vec::new(),
+            published: None,
+            lang: PreparedLanguage::from(&[][..]),
+        };
+        // Contributor which is currently read, with its contrib-type
+        let mut contributor : Option<(String, Person)> = None;
+        let mut date : Option<(Option<String>, Option<String>, Option<String>)> = None;
+
+        loop{
+            let event = reader.read_event().map_err(|e| JatsError::InvalidXml(e.to_string()))?;
+            match event{
+                Event::Start(element) => {
+                    let name = element.local_name().as_ref().to_string();
+                    match name.as_str(){
+                        "article-meta" => found = true,
+                        "contrib" => contributor = Some((attribute(&element, "contrib-type")?.unwrap_or_else(|| "author".to_string()), Person{
+                            id: None,
+                            first_names: None,
+                            last_names: String::new(),
+                            orcid: None,
+                            gnd: None,
+                            bios: None,
+                            ror: None,
+                            name_prefix: None,
+                            name_suffix: None,
+                            particle: None,
+                        })),
+                        // Only the first date is used, e.g. if there are an electronic and a print one
+                        "pub-date" if metadata.published.is_none() => date = Some((None, None, None)),
+                        "article-title" | "subtitle" | "alt-title" | "article-id" | "contrib-id" | "surname" | "given-names" | "prefix" | "suffix" | "year" | "month" | "day" => {
+                            let raw = reader.read_text(element.name()).map_err(|e| JatsError::InvalidXml(e.to_string()))?;
+                            let text = quick_xml::escape::unescape(&raw).map_err(|e| JatsError::InvalidXml(e.to_string()))?.trim().to_string();
+
+                            match (name.as_str(), &mut contributor, &mut date){
+                                ("article-title", _, _) => {
+                                    if let Some(language) = attribute(&element, "xml:lang")?{
+                                        metadata.lang = PreparedLanguage::from(Language::from_iso_code(&language));
+                                    }
+                                    title = Some(text);
+                                },
+                                ("subtitle", _, _) => metadata.subtitle = Some(text),
+                                ("alt-title", _, _) if attribute(&element, "alt-title-type")?.as_deref() == Some("toc") => metadata.toc_title = Some(text),
+                                ("article-id", _, _) => {
+                                    let identifier_type = attribute(&element, "pub-id-type")?.map(|pub_id_type| identifier_type_from_jats(&pub_id_type))
+                                        .unwrap_or(IdentifierType::Other("other".to_string()));
+                                    metadata.identifiers.push(Identifier::new(identifier_type, text, None));
+                                },
+                                ("contrib-id", Some((_, person)), _) if attribute(&element, "contrib-id-type")?.as_deref() == Some("orcid") => {
+                                    person.orcid = Some(Identifier::new(IdentifierType::ORCID, text, None));
+                                },
+                                ("surname", Some((_, person)), _) => person.last_names = text,
+                                ("given-names", Some((_, person)), _) => person.first_names = Some(text),
+                                ("prefix", Some((_, person)), _) => person.name_prefix = Some(text),
+                                ("suffix", Some((_, person)), _) => person.name_suffix = Some(text),
+                                ("year", _, Some((year, _, _))) => *year = Some(text),
+                                ("month", _, Some((_, month, _))) => *month = Some(text),
+                                ("day", _, Some((_, _, day))) => *day = Some(text),
+                                _ => {},
+                            }
+                        },
+                        _ => {},
+                    }
+                },
+                Event::Empty(element) if element.local_name().as_ref() == "self-uri" => metadata.web_url = attribute(&element, "xlink:href")?,
+                Event::End(element) => match element.local_name().as_ref(){
+                    "contrib" => if let Some((role, person)) = contributor.take(){
+                        match role.as_str(){
+                            "editor" => metadata.editors.push(person),
+                            _ => metadata.authors.push(person),
+                        }
+                    },
+                    "pub-date" => if let Some((Some(year), month, day)) = date.take(){
+                        let pad = |part: String| part.parse::<u8>().map(|number| format!("{:02}", number)).unwrap_or(part);
+                        let parts = [Some(year), month.map(pad), day.map(pad)];
+                        metadata.published = Some(parts.into_iter().map_while(|part| part).collect::<Vec<String>>().join("-"));
+                    },
+                    "article-meta" => break,
+                    _ => {},
+                },
+                Event::Eof => break,
+                _ => {},
+            }
+        }
+
+        if !found{
+            return Err(JatsError::MissingArticleMeta)
+        }
+        metadata.title = title.ok_or(JatsError::MissingTitle)?;
+
+        Ok(metadata)
+    }
+}
+
+/// pub-id-type of an identifier in JATS
+#[cfg(feature = "jats")]
+fn jats_pub_id_type(identifier_type: &IdentifierType) -> &str{
+    match identifier_type{
+        IdentifierType::DOI => "doi",
+        IdentifierType::ISBN => "isbn",
+        IdentifierType::ISSN => "issn",
+        IdentifierType::URL => "uri",
+        IdentifierType::URN => "urn",
+        IdentifierType::ORCID => "orcid",
+        IdentifierType::ROR => "ror",
+        IdentifierType::GND => "gnd",
+        IdentifierType::Other(other) => other,
+    }
+}
+
+/// Reverse of [jats_pub_id_type], unknown types become [IdentifierType::Other]
+#[cfg(feature = "jats")]
+fn identifier_type_from_jats(pub_id_type: &str) -> IdentifierType{
+    match pub_id_type.to_ascii_lowercase().as_str(){
+        "doi" => IdentifierType::DOI,
+        "isbn" => IdentifierType::ISBN,
+        "issn" => IdentifierType::ISSN,
+        "uri" => IdentifierType::URL,
+        "urn" => IdentifierType::URN,
+        "orcid" => IdentifierType::ORCID,
+        "ror" => IdentifierType::ROR,
+        "gnd" => IdentifierType::GND,
+        _ => IdentifierType::Other(pub_id_type.to_string()),
+    }
+}
+
+/// Error of [PreparedSectionMetadata::from_jats_xml]
+#[cfg(feature = "jats")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum JatsError{
+    /// The XML isn't well-formed
+    InvalidXml(String),
+    /// There is no `<article-meta>` element
+    MissingArticleMeta,
+    /// The `<article-meta>` has no `<article-title>`
+    MissingTitle,
+}
+
+#[cfg(feature = "jats")]
+impl Display for JatsError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            JatsError::InvalidXml(error) => write!(f, "Invalid JATS XML: {}", error),
+            JatsError::MissingArticleMeta => write!(f, "No article-meta element found."),
+            JatsError::MissingTitle => write!(f, "The article-meta has no article-title."),
+        }
+    }
+}
+
+#[cfg(feature = "jats")]
+impl std::error::Error for JatsError {}
+
+impl PreparedMetadata{
+    /// Starts building metadata with the given title, all other fields are empty until set, see [PreparedMetadataBuilder]
+    pub fn builder(title: impl Into<String>) -> PreparedMetadataBuilder{
+        PreparedMetadataBuilder::new(title)
+    }
+
+    /// Stores the page count reported by the rendering server after a successful render (see [crate::RenderingResult::page_count])
+    pub fn set_page_count(&mut self, pages: u32){
+        self.number_of_pages = Some(pages);
+    }
+
+    /// Merges keywords describing the same subject, see [Keyword::matches]
+    ///
+    /// The first occurrence keeps its position. If it has no GND but a duplicate has one, it is replaced by that duplicate.
+    pub fn dedupe_keywords(&mut self){
+        let Some(keywords) = self.keywords.take() else {
+            return
+        };
+
+        let mut deduped : Vec<Keyword> = Vec::with_capacity(keywords.len());
+        for keyword in keywords{
+            match deduped.iter_mut().find(|existing| existing.matches(&keyword)){
+                Some(existing) => {
+                    if existing.gnd.is_none() && keyword.gnd.is_some(){
+                        *existing = keyword;
+                    }
+                },
+                None => deduped.push(keyword),
+            }
+        }
+
+        self.keywords = Some(deduped);
+    }
+
+    /// Formats the metadata as BibTeX @book entry with the given citation key
+    ///
+    /// Contains title (with subtitle), authors and editors (last name first, joined with "and"), the year of publication,
+    /// publisher, edition, series, volume, all ISBNs, the first DOI and the web url. Missing fields are omitted,
+    /// special characters are escaped.
+    pub fn to_bibtex(&self, key: &str) -> String{
+        let names = |persons: &[Person]| (!persons.is_empty()).then(|| persons.iter().map(|person| person.display_name(NameOrder::LastFirst)).collect::<Vec<String>>().join(" and "));
+        let title = match &self.subtitle{
+            Some(subtitle) => format!("{}: {}", self.title, subtitle),
+            None => self.title.clone(),
+        };
+        let isbns = self.identifiers_of_type(&IdentifierType::ISBN).map(|isbn| isbn.value.as_str()).collect::<Vec<&str>>().join(", ");
+
+        let fields = [
+            ("title", Some(title)),
+            ("author", names(&self.authors)),
+            ("editor", names(&self.editors)),
+            ("year", self.published.as_deref().and_then(split_date).map(|(year, _, _)| year.to_string())),
+            ("publisher", self.publisher.clone()),
+            ("edition", self.edition.clone()),
+            ("series", self.series.clone()),
+            ("volume", self.volume.clone()),
+            ("isbn", (!isbns.is_empty()).then_some(isbns)),
+            ("doi", self.identifiers_of_type(&IdentifierType::DOI).next().map(|doi| doi.value.clone())),
+            ("url", self.web_url.clone()),
+        ];
+
+        let mut entry = format!("@book{{{},\n", key);
+        for (field, value) in fields{
+            if let Some(value) = value.filter(|value| !value.trim().is_empty()){
+                entry.push_str(&format!("  {} = {{{}}},\n", field, escape_bibtex(value.trim())));
+            }
+        }
+        entry.push_str("}\n");
+        entry
+    }
+
+    /// Returns all identifiers of the book with the given type
+    pub fn identifiers_of_type<'a>(&'a self, identifier_type: &'a IdentifierType) -> impl Iterator<Item = &'a Identifier> + 'a{
+        self.identifiers.iter().flatten().filter(move |identifier| &identifier.identifier_type == identifier_type)
+    }
+}
+
+#[cfg(feature = "csl")]
+impl PreparedMetadata{
+    /// Converts the metadata into a CSL-JSON item of type book, e.g. for citation tools
+    ///
+    /// Authors and editors are added as CSL names, the publication date as issued date-parts,
+    /// the first DOI and ISBN identifiers as DOI / ISBN and the short abstract as abstract. Missing fields are omitted.
+    /// The id of the item is the DOI, the ISBN or the title (in this order).
+    pub fn to_csl_json(&self) -> serde_json::Value{
+        use serde_json::{json, Map, Value};
+
+        let doi = self.identifiers_of_type(&IdentifierType::DOI).next().map(|doi| doi.value.clone());
+        let isbn = self.identifiers_of_type(&IdentifierType::ISBN).next().map(|isbn| isbn.value.clone());
+        let title = match &self.subtitle{
+            Some(subtitle) => format!("{}: {}", self.title, subtitle),
+            None => self.title.clone(),
+        };
+
+        let mut item = Map::new();
+        item.insert("id".to_string(), json!(doi.clone().or(isbn.clone()).unwrap_or(self.title.clone())));
+        item.insert("type".to_string(), json!("book"));
+        item.insert("title".to_string(), json!(title));
+
+        let mut insert = |key: &str, value: Option<Value>| {
+            if let Some(value) = value{
+                item.insert(key.to_string(), value);
+            }
+        };
+        insert("author", (!self.authors.is_empty()).then(|| Value::Array(self.authors.iter().map(csl_name).collect())));
+        insert("editor", (!self.editors.is_empty()).then(|| Value::Array(self.editors.iter().map(csl_name).collect())));
+        insert("issued", self.published.as_deref().and_then(split_date).map(|(year, month, day)| {
+            let date_parts : Vec<i32> = [Some(year), month.map(i32::from), day.map(i32::from)].into_iter().map_while(|part| part).collect();
+            json!({"date-parts": [date_parts]})
+        }));
+        insert("publisher", self.publisher.as_ref().map(|publisher| json!(publisher)));
+        insert("edition", self.edition.as_ref().map(|edition| json!(edition)));
+        insert("collection-title", self.series.as_ref().map(|series| json!(series)));
+        insert("collection-number", self.volume.as_ref().map(|volume| json!(volume)));
+        insert("number-of-pages", self.number_of_pages.map(|pages| json!(pages)));
+        insert("language", self.languages.as_ref().and_then(|languages| languages.first()).map(|language| json!(language.to_iso_code())));
+        insert("DOI", doi.map(Value::String));
+        insert("ISBN", isbn.map(Value::String));
+        insert("URL", self.web_url.as_ref().map(|url| json!(url)));
+        insert("abstract", self.short_abstract.as_ref().map(|short_abstract| json!(short_abstract)));
+
+        Value::Object(item)
+    }
+}
+
+/// CSL name object of a person, the particle is a dropping particle, so the person is sorted under the last name
+#[cfg(feature = "csl")]
+fn csl_name(person: &Person) -> serde_json::Value{
+    let mut name = serde_json::Map::new();
+    name.insert("family".to_string(), serde_json::json!(person.last_names));
+    let optional_parts = [("given", &person.first_names), ("dropping-particle", &person.particle), ("suffix", &person.name_suffix)];
+    for (key, value) in optional_parts{
+        if let Some(value) = value.as_deref().map(str::trim).filter(|value| !value.is_empty()){
+            name.insert(key.to_string(), serde_json::json!(value));
+        }
+    }
+
+    serde_json::Value::Object(name)
+}
+
+/// Builds [PreparedMetadata] without listing every empty field, e.g. `PreparedMetadata::builder("Title").publisher("Verfassungsbooks").build()`
+///
+/// Setters for lists with a singular name (e.g. [PreparedMetadataBuilder::author]) append one entry, the plural ones replace the list.
+pub struct PreparedMetadataBuilder{
+    metadata: PreparedMetadata,
+}
+
+impl PreparedMetadataBuilder{
+    pub fn new(title: impl Into<String>) -> Self{
+        PreparedMetadataBuilder{
+            metadata: PreparedMetadata{
+                title: title.into(),
+                subtitle: None,
+                authors: Vec::new(),
+                editors: Vec::new(),
+                web_url: None,
+                identifiers: None,
+                published: None,
+                languages: None,
+                number_of_pages: None,
+                short_abstract: None,
+                long_abstract: None,
+                keywords: None,
+                ddc: None,
+                license: None,
+                series: None,
+                volume: None,
+                edition: None,
+                publisher: None,
+                title_translations: Vec::new(),
+            },
+        }
+    }
+
+    pub fn subtitle(mut self, subtitle: impl Into<String>) -> Self{
+        self.metadata.subtitle = Some(subtitle.into());
+        self
+    }
+
+    pub fn author(mut self, author: Person) -> Self{
+        self.metadata.authors.push(author);
+        self
+    }
+
+    pub fn authors(mut self, authors: Vec<Person>) -> Self{
+        self.metadata.authors = authors;
+        self
+    }
+
+    pub fn editor(mut self, editor: Person) -> Self{
+        self.metadata.editors.push(editor);
+        self
+    }
+
+    pub fn editors(mut self, editors: Vec<Person>) -> Self{
+        self.metadata.editors = editors;
+        self
+    }
+
+    pub fn web_url(mut self, web_url: impl Into<String>) -> Self{
+        self.metadata.web_url = Some(web_url.into());
+        self
+    }
+
+    pub fn identifier(mut self, identifier: Identifier) -> Self{
+        self.metadata.identifiers.get_or_insert_with(Vec::new).push(identifier);
+        self
+    }
+
+    pub fn identifiers(mut self, identifiers: Vec<Identifier>) -> Self{
+        self.metadata.identifiers = Some(identifiers);
+        self
+    }
+
+    /// Publication date in the form YYYY, YYYY-MM or YYYY-MM-DD, see [normalize_publication_date] for other formats
+    pub fn published(mut self, published: impl Into<String>) -> Self{
+        self.metadata.published = Some(published.into());
+        self
+    }
+
+    pub fn language(mut self, language: Language) -> Self{
+        self.metadata.languages.get_or_insert_with(Vec::new).push(language);
+        self
+    }
+
+    pub fn languages(mut self, languages: Vec<Language>) -> Self{
+        self.metadata.languages = Some(languages);
+        self
+    }
+
+    pub fn number_of_pages(mut self, number_of_pages: u32) -> Self{
+        self.metadata.number_of_pages = Some(number_of_pages);
+        self
+    }
+
+    pub fn short_abstract(mut self, short_abstract: impl Into<String>) -> Self{
+        self.metadata.short_abstract = Some(short_abstract.into());
+        self
+    }
+
+    pub fn long_abstract(mut self, long_abstract: impl Into<String>) -> Self{
+        self.metadata.long_abstract = Some(long_abstract.into());
+        self
+    }
+
+    pub fn keyword(mut self, keyword: Keyword) -> Self{
+        self.metadata.keywords.get_or_insert_with(Vec::new).push(keyword);
+        self
+    }
+
+    pub fn keywords(mut self, keywords: Vec<Keyword>) -> Self{
+        self.metadata.keywords = Some(keywords);
+        self
+    }
+
+    pub fn ddc(mut self, ddc: impl Into<String>) -> Self{
+        self.metadata.ddc = Some(ddc.into());
+        self
+    }
+
+    /// Accepts a [License] as well as a [PreparedLicense]
+    pub fn license(mut self, license: impl Into<PreparedLicense>) -> Self{
+        self.metadata.license = Some(license.into());
+        self
+    }
+
+    pub fn series(mut self, series: impl Into<String>) -> Self{
+        self.metadata.series = Some(series.into());
+        self
+    }
+
+    pub fn volume(mut self, volume: impl Into<String>) -> Self{
+        self.metadata.volume = Some(volume.into());
+        self
+    }
+
+    pub fn edition(mut self, edition: impl Into<String>) -> Self{
+        self.metadata.edition = Some(edition.into());
+        self
+    }
+
+    pub fn publisher(mut self, publisher: impl Into<String>) -> Self{
+        self.metadata.publisher = Some(publisher.into());
+        self
+    }
+
+    pub fn title_translation(mut self, language: Language, title: impl Into<String>, subtitle: Option<String>) -> Self{
+        self.metadata.title_translations.push(TitleTranslation{
+            language,
+            title: title.into(),
+            subtitle,
+        });
+        self
+    }
+
+    pub fn build(self) -> PreparedMetadata{
+        self.metadata
+    }
+}
+
+/// Escapes characters with a special meaning in BibTeX values
+fn escape_bibtex(value: &str) -> String{
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars(){
+        if matches!(c, '{' | '}' | '&' | '%' | '$' | '#' | '_'){
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Splits a publication date into year, month and day, see [parse_publication_date]
+fn split_date(date: &str) -> Option<(i32, Option<u8>, Option<u8>)>{
+    parse_publication_date(date).map(|date| (date.year, date.month, date.day))
+}
+
+/// Date of publication which may only be known to the year or month, see [parse_publication_date]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PartialDate{
+    pub year: i32,
+    /// 1-12
+    pub month: Option<u8>,
+    /// 1-31, only set if the month is set
+    pub day: Option<u8>,
+}
+
+/// Formats the date as ISO 8601, i.e. YYYY, YYYY-MM or YYYY-MM-DD
+impl Display for PartialDate{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        if let Some(month) = self.month{
+            write!(f, "-{:02}", month)?;
+            if let Some(day) = self.day{
+                write!(f, "-{:02}", day)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a publication date in one of the common formats into year, month and day
+///
+/// Accepts ISO 8601 (e.g. "2024", "2024-03", "2024-03-05", also with a time appended), "2024/03/05", "03/2024", "03.2024",
+/// German dates like "05.03.2024" and month names in English or German, full or abbreviated (e.g. "March 2024", "5 Mar 2024",
+/// "March 5, 2024" or "5. März 2024"). Years have to be written with four digits.
+/// Returns None for anything else and for dates which don't exist (e.g. "2023-02-29"), instead of guessing.
+/// "03/05/2024" could be the 3rd of May or the 5th of March, so day/month orders with "/" or "-" are only accepted if the day is larger than 12.
+pub fn parse_publication_date(date: &str) -> Option<PartialDate>{
+    let date = date.trim();
+    // Time of an ISO 8601 date time, e.g. "2024-03-05T10:00:00Z"
+    let date = match date.find(['T', ' ']){
+        Some(10) if date.as_bytes()[4] == b'-' => &date[..10],
+        _ => date,
+    };
+
+    let (year, month, day) = if date.chars().all(|c| c.is_ascii_digit() || matches!(c, '-' | '/' | '.')){
+        parse_numeric_date(date)?
+    }else{
+        parse_named_month_date(date)?
+    };
+
+    let date = PartialDate{
+        year,
+        month,
+        day,
+    };
+    date_exists(&date).then_some(date)
+}
+
+/// Normalizes a publication date to ISO 8601 (YYYY, YYYY-MM or YYYY-MM-DD), None if it can't be parsed with [parse_publication_date]
+pub fn normalize_publication_date(date: &str) -> Option<String>{
+    parse_publication_date(date).map(|date| date.to_string())
+}
+
+/// Parses dates only consisting of digits and one kind of separator, e.g. "2024-03-05", "03/2024" or "05.03.2024"
+fn parse_numeric_date(date: &str) -> Option<(i32, Option<u8>, Option<u8>)>{
+    let separator = date.chars().find(|c| !c.is_ascii_digit());
+    let parts : Vec<&str> = match separator{
+        Some(separator) => date.split(separator).collect(),
+        None => vec![date],
+    };
+    if parts.iter().any(|part| part.is_empty() || !part.chars().all(|c| c.is_ascii_digit())){
+        return None
+    }
+
+    let is_year = |part: &str| part.len() == 4;
+    let number = |part: &str| part.parse::<u8>().ok();
+
+    match (parts.as_slice(), separator){
+        ([year], _) if is_year(year) => Some((year.parse().ok()?, None, None)),
+        ([year, month], Some('-' | '/')) if is_year(year) => Some((year.parse().ok()?, Some(number(month)?), None)),
+        ([month, year], _) if is_year(year) && month.len() <= 2 => Some((year.parse().ok()?, Some(number(month)?), None)),
+        ([year, month, day], Some('-' | '/')) if is_year(year) => Some((year.parse().ok()?, Some(number(month)?), Some(number(day)?))),
+        // German order, which is the only one written with dots
+        ([day, month, year], Some('.')) if is_year(year) => Some((year.parse().ok()?, Some(number(month)?), Some(number(day)?))),
+        ([first, second, year], _) if is_year(year) => {
+            let (first, second) = (number(first)?, number(second)?);
+            match (first > 12, second > 12){
+                (true, false) => Some((year.parse().ok()?, Some(second), Some(first))),
+                (false, true) => Some((year.parse().ok()?, Some(first), Some(second))),
+                _ => None,
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Parses dates with a month name, e.g. "March 2024", "5. März 2024" or "March 5, 2024"
+fn parse_named_month_date(date: &str) -> Option<(i32, Option<u8>, Option<u8>)>{
+    let mut year = None;
+    let mut month = None;
+    let mut day = None;
+
+    for token in date.split(|c: char| c.is_whitespace() || c == ',' || c == '.').filter(|token| !token.is_empty()){
+        if token.chars().all(|c| c.is_ascii_digit()){
+            match token.len(){
+                4 if year.is_none() => year = Some(token.parse().ok()?),
+                1 | 2 if day.is_none() => day = Some(token.parse().ok()?),
+                _ => return None,
+            }
+        }else if month.is_none(){
+            month = Some(month_from_name(token)?);
+        }else{
+            return None
+        }
+    }
+
+    // A day without a month (e.g. "5 2024") is no date
+    Some((year?, Some(month?), day))
+}
+
+/// Number of the month from its English or German name, full or abbreviated (e.g. "March", "Mar", "März", "Mrz"), case-insensitive
+fn month_from_name(name: &str) -> Option<u8>{
+    const MONTHS : [&[&str]; 12] = [
+        &["january", "jan", "januar", "jän", "jänner"],
+        &["february", "feb", "februar"],
+        &["march", "mar", "märz", "mär", "mrz"],
+        &["april", "apr"],
+        &["may", "mai"],
+        &["june", "jun", "juni"],
+        &["july", "jul", "juli"],
+        &["august", "aug"],
+        &["september", "sep", "sept"],
+        &["october", "oct", "oktober", "okt"],
+        &["november", "nov"],
+        &["december", "dec", "dezember", "dez"],
+    ];
+
+    let name = name.to_lowercase();
+    MONTHS.iter().position(|names| names.contains(&name.as_str())).map(|index| index as u8 + 1)
+}
+
+/// Whether the month and day exist, e.g. no 2023-02-29
+fn date_exists(date: &PartialDate) -> bool{
+    let Some(month) = date.month else {
+        return date.day.is_none()
+    };
+    let leap_year = date.year % 4 == 0 && (date.year % 100 != 0 || date.year % 400 == 0);
+    let days = match month{
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap_year => 29,
+        2 => 28,
+        _ => return false,
+    };
+
+    match date.day{
+        Some(day) => (1..=days).contains(&day),
+        None => true,
+    }
+}
+
+/// Represents a Keyword, optionally with a GND ID
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub struct Keyword{
+    pub title: String,
+    pub gnd: Option<Identifier>,
+}
+
+impl Keyword{
+    /// Checks whether both keywords describe the same subject
+    ///
+    /// Titles are compared case-insensitively and without surrounding whitespace.
+    /// Keywords with different GND ids never match, even with the same title, since they are homonyms of different subjects.
+    pub fn matches(&self, other: &Keyword) -> bool{
+        let conflicting_gnd = self.gnd.is_some() && other.gnd.is_some() && !same_identifier(&self.gnd, &other.gnd);
+        !conflicting_gnd && self.title.trim().to_lowercase() == other.title.trim().to_lowercase()
+    }
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Hash)]
+pub struct PreparedLicense{
+    CC0: bool,
+    CC_BY_4: bool,
+    CC_BY_SA_4: bool,
+    CC_BY_ND_4: bool,
+    CC_BY_NC_4: bool,
+    CC_BY_NC_SA_4: bool,
+    CC_BY_NC_ND_4: bool,
+    other: String,
+}
+
+impl PreparedLicense{
+    /// Returns the active license, reverse of `From<License>`
+    pub fn as_license(&self) -> License{
+        let flags = [
+            (self.CC0, License::CC0),
+            (self.CC_BY_4, License::CC_BY_4),
+            (self.CC_BY_SA_4, License::CC_BY_SA_4),
+            (self.CC_BY_ND_4, License::CC_BY_ND_4),
+            (self.CC_BY_NC_4, License::CC_BY_NC_4),
+            (self.CC_BY_NC_SA_4, License::CC_BY_NC_SA_4),
+            (self.CC_BY_NC_ND_4, License::CC_BY_NC_ND_4),
+        ];
+
+        flags.into_iter()
+            .find_map(|(active, license)| active.then_some(license))
+            .unwrap_or_else(|| License::Other(self.other.clone()))
+    }
+}
+
+impl Display for PreparedLicense{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_license())
+    }
+}
+
+/// Holds all different (CC) licenses or a custom license
+#[derive(Deserialize, Serialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub enum License{
+    CC0,
+    CC_BY_4,
+    CC_BY_SA_4,
+    CC_BY_ND_4,
+    CC_BY_NC_4,
+    CC_BY_NC_SA_4,
+    CC_BY_NC_ND_4,
+    Other(String),
+}
+
+/// SPDX identifiers of the known licenses
+const LICENSE_SPDX_IDS: [(License, &str); 7] = [
+    (License::CC0, "CC0-1.0"),
+    (License::CC_BY_4, "CC-BY-4.0"),
+    (License::CC_BY_SA_4, "CC-BY-SA-4.0"),
+    (License::CC_BY_ND_4, "CC-BY-ND-4.0"),
+    (License::CC_BY_NC_4, "CC-BY-NC-4.0"),
+    (License::CC_BY_NC_SA_4, "CC-BY-NC-SA-4.0"),
+    (License::CC_BY_NC_ND_4, "CC-BY-NC-ND-4.0"),
+];
+
+impl License{
+    /// Full name of the license, e.g. "Creative Commons Attribution 4.0 International", the string itself for [License::Other]
+    pub fn full_name(&self) -> &str{
+        match self{
+            License::CC0 => "Creative Commons Zero 1.0 Universal",
+            License::CC_BY_4 => "Creative Commons Attribution 4.0 International",
+            License::CC_BY_SA_4 => "Creative Commons Attribution-ShareAlike 4.0 International",
+            License::CC_BY_ND_4 => "Creative Commons Attribution-NoDerivatives 4.0 International",
+            License::CC_BY_NC_4 => "Creative Commons Attribution-NonCommercial 4.0 International",
+            License::CC_BY_NC_SA_4 => "Creative Commons Attribution-NonCommercial-ShareAlike 4.0 International",
+            License::CC_BY_NC_ND_4 => "Creative Commons Attribution-NonCommercial-NoDerivatives 4.0 International",
+            License::Other(other) => other,
+        }
+    }
+
+    /// Short code of the license as used in imprints, e.g. "CC BY 4.0", the string itself for [License::Other]
+    pub fn short_code(&self) -> &str{
+        match self{
+            License::CC0 => "CC0 1.0",
+            License::CC_BY_4 => "CC BY 4.0",
+            License::CC_BY_SA_4 => "CC BY-SA 4.0",
+            License::CC_BY_ND_4 => "CC BY-ND 4.0",
+            License::CC_BY_NC_4 => "CC BY-NC 4.0",
+            License::CC_BY_NC_SA_4 => "CC BY-NC-SA 4.0",
+            License::CC_BY_NC_ND_4 => "CC BY-NC-ND 4.0",
+            License::Other(other) => other,
+        }
+    }
+
+    /// SPDX identifier of the license (e.g. "CC-BY-SA-4.0"), the string itself for [License::Other]
+    pub fn to_spdx(&self) -> String{
+        match self{
+            License::Other(other) => other.clone(),
+            license => LICENSE_SPDX_IDS.iter().find(|(known, _)| known == license).map(|(_, id)| id.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Full name and short code, e.g. "Creative Commons Attribution 4.0 International (CC BY 4.0)", only the string for [License::Other]
+impl Display for License{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            License::Other(other) => write!(f, "{}", other),
+            license => write!(f, "{} ({})", license.full_name(), license.short_code()),
+        }
+    }
+}
+
+/// Parses a SPDX identifier (case-insensitive), unknown identifiers become [License::Other]
+impl FromStr for License{
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = s.trim();
+        Ok(LICENSE_SPDX_IDS.iter()
+            .find(|(_, known_id)| known_id.eq_ignore_ascii_case(id))
+            .map(|(license, _)| license.clone())
+            .unwrap_or_else(|| License::Other(s.to_string())))
+    }
+}
+
+/// implement from License -> PreparedLicense
+impl From<License> for PreparedLicense{
+    fn from(license: License) -> Self{
+        match license{
+            License::CC0 => PreparedLicense{CC0: true, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
+            License::CC_BY_4 => PreparedLicense{CC0: false, CC_BY_4: true, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
+            License::CC_BY_SA_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: true, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
+            License::CC_BY_ND_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: true, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
+            License::CC_BY_NC_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: true, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other: String::new()},
+            License::CC_BY_NC_SA_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: true, CC_BY_NC_ND_4: false, other: String::new()},
+            License::CC_BY_NC_ND_4 => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: true, other: String::new()},
+            License::Other(other) => PreparedLicense{CC0: false, CC_BY_4: false, CC_BY_SA_4: false, CC_BY_ND_4: false, CC_BY_NC_4: false, CC_BY_NC_SA_4: false, CC_BY_NC_ND_4: false, other},
+        }
+    }
+}
 
 /// Represents a single entry in the Table of Contents
-#[derive(Serialize, Deserialize, Encode, Decode)]
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Hash)]
 pub struct TocEntry{
     pub title: String,
     pub level: u32,
@@ -230,7 +2316,7 @@ pub struct TocEntry{
     pub children: Vec<TocEntry>
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Hash)]
 pub struct PreparedSection{
     #[bincode(with_serde)]
     pub id: uuid::Uuid,
@@ -241,7 +2327,120 @@ pub struct PreparedSection{
     pub endnotes: Vec<PreparedEndnote>
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
+fn build_toc_entries(sections: &[PreparedSection], level: u32) -> Vec<TocEntry>{
+    sections.iter().filter(|section| section.visible_in_toc).map(|section| TocEntry{
+        title: section.metadata.toc_title.clone().unwrap_or_else(|| section.metadata.title.clone()),
+        level,
+        id: section.id,
+        children: build_toc_entries(&section.sub_sections, level + 1),
+    }).collect()
+}
+
+fn renumber_section_endnotes(sections: &mut [PreparedSection], mode: EndnoteNumbering, next: &mut usize, mapping: &mut HashMap<uuid::Uuid, HashMap<usize, usize>>){
+    for section in sections{
+        if mode == EndnoteNumbering::RestartPerSection{
+            *next = 1;
+        }
+
+        let section_mapping = mapping.entry(section.id).or_default();
+        for endnote in section.endnotes.iter_mut(){
+            section_mapping.insert(endnote.num, *next);
+            endnote.num = *next;
+            *next += 1;
+        }
+
+        renumber_section_endnotes(&mut section.sub_sections, mode, next, mapping);
+    }
+}
+
+/// How [PreparedProject::renumber_endnotes] numbers the endnotes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndnoteNumbering{
+    /// One sequence from 1 for the whole book
+    ContinuousAcrossBook,
+    /// Every section (including sub sections) starts again at 1
+    RestartPerSection,
+}
+
+/// Depth-first iterator over a section tree, see [PreparedProject::sections_iter]
+struct SectionsIter<'a>{
+    stack: Vec<std::slice::Iter<'a, PreparedSection>>,
+}
+
+impl<'a> Iterator for SectionsIter<'a>{
+    type Item = &'a PreparedSection;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let sections = self.stack.last_mut()?;
+            match sections.next(){
+                Some(section) => {
+                    self.stack.push(section.sub_sections.iter());
+                    return Some(section)
+                },
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+fn collect_content_blocks_mut<'a>(sections: &'a mut [PreparedSection], blocks: &mut Vec<&'a mut PreparedContentBlock>){
+    for section in sections{
+        blocks.extend(section.children.iter_mut());
+        collect_content_blocks_mut(&mut section.sub_sections, blocks);
+    }
+}
+
+impl PreparedSection{
+    /// Number of words in the content blocks and endnotes of this section, without its sub sections
+    /// Only the visible text is counted (see [PreparedContentBlock::plain_text]), standalone punctuation like "–" isn't a word.
+    pub fn word_count(&self) -> usize{
+        self.plain_texts().map(|text| text.split_whitespace().filter(|word| word.chars().any(char::is_alphanumeric)).count()).sum()
+    }
+
+    /// Number of characters in the content blocks and endnotes of this section, without its sub sections
+    /// Spaces are counted as well, but consecutive whitespace only once.
+    pub fn char_count(&self) -> usize{
+        self.plain_texts().map(|text| text.split_whitespace().collect::<Vec<&str>>().join(" ").chars().count()).sum()
+    }
+
+    fn plain_texts(&self) -> impl Iterator<Item = String> + '_{
+        self.children.iter().map(PreparedContentBlock::plain_text)
+            .chain(self.endnotes.iter().map(|endnote| html_to_text(&endnote.content)))
+    }
+
+    fn count_block_types(&self, counts: &mut HashMap<BlockType, usize>){
+        for block in &self.children{
+            *counts.entry(block.block_type.clone()).or_insert(0) += 1;
+        }
+        for sub_section in &self.sub_sections{
+            sub_section.count_block_types(counts);
+        }
+    }
+
+    fn validate(&self, issues: &mut Vec<ValidationIssue>){
+        if self.metadata.title.trim().is_empty(){
+            issues.push(ValidationIssue::EmptySectionTitle(self.id));
+        }
+
+        let persons = self.metadata.authors.iter().chain(self.metadata.editors.iter());
+        validate_identifiers(Some(self.id), self.metadata.identifiers.iter(), persons, issues);
+        for sub_section in &self.sub_sections{
+            sub_section.validate(issues);
+        }
+    }
+
+    fn collect_contributors<'a>(&'a self, persons: &mut Vec<&'a Person>){
+        persons.extend(self.metadata.authors.iter().chain(self.metadata.editors.iter()));
+        for sub_section in &self.sub_sections{
+            sub_section.collect_contributors(persons);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Hash)]
 pub struct PreparedEndnote{
     pub num: usize,
     #[bincode(with_serde)]
@@ -249,7 +2448,7 @@ pub struct PreparedEndnote{
     pub content: String,
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Hash)]
 pub struct PreparedSectionMetadata{
     pub title: String,
     pub subtitle: Option<String>,
@@ -262,25 +2461,946 @@ pub struct PreparedSectionMetadata{
     pub lang: PreparedLanguage,
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
+/// Languages of a section, the flags make it easy for templates to check for a specific language
+///
+/// Peers older than protocol version 7 only know German and English, they get the layout of [PreparedLanguageV1].
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct PreparedLanguage{
     pub de: bool,
     pub en: bool,
+    #[serde(default)]
+    pub fr: bool,
+    #[serde(default)]
+    pub it: bool,
+    #[serde(default)]
+    pub es: bool,
+    /// ISO 639-1 codes of all languages, including the ones without a flag
+    #[serde(default)]
+    pub codes: Vec<String>,
+}
+
+impl Encode for PreparedLanguage{
+    fn encode<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> Result<(), bincode::error::EncodeError> {
+        if crate::wire_version() < 7 {
+            return PreparedLanguageV1{
+                de: self.de,
+                en: self.en,
+            }.encode(encoder)
+        }
+        self.de.encode(encoder)?;
+        self.en.encode(encoder)?;
+        self.fr.encode(encoder)?;
+        self.it.encode(encoder)?;
+        self.es.encode(encoder)?;
+        self.codes.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for PreparedLanguage{
+    fn decode<D: bincode::de::Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
+        if crate::wire_version() < 7 {
+            return PreparedLanguageV1::decode(decoder).map(PreparedLanguage::from)
+        }
+        Ok(PreparedLanguage{
+            de: Decode::decode(decoder)?,
+            en: Decode::decode(decoder)?,
+            fr: Decode::decode(decoder)?,
+            it: Decode::decode(decoder)?,
+            es: Decode::decode(decoder)?,
+            codes: Decode::decode(decoder)?,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(PreparedLanguage);
+
+impl PreparedLanguage{
+    /// Returns all languages, falls back to the flags for data which has no codes
+    pub fn languages(&self) -> Vec<Language>{
+        if !self.codes.is_empty(){
+            return self.codes.iter().map(|code| Language::from_iso_code(code)).collect()
+        }
+
+        [(self.de, Language::DE), (self.en, Language::EN), (self.fr, Language::FR), (self.it, Language::IT), (self.es, Language::ES)].into_iter()
+            .filter_map(|(active, language)| active.then_some(language))
+            .collect()
+    }
+}
+
+/// [PreparedLanguage] before protocol version 7, only with the German and English flags
+#[derive(Serialize, Deserialize, Debug, Encode, Decode, Clone, PartialEq, Eq, Hash)]
+pub struct PreparedLanguageV1{
+    pub de: bool,
+    pub en: bool,
+}
+
+impl From<PreparedLanguageV1> for PreparedLanguage{
+    fn from(language: PreparedLanguageV1) -> Self{
+        PreparedLanguage{
+            de: language.de,
+            en: language.en,
+            fr: false,
+            it: false,
+            es: false,
+            codes: Vec::new(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode)]
+impl From<&[Language]> for PreparedLanguage{
+    fn from(languages: &[Language]) -> Self{
+        PreparedLanguage{
+            de: languages.contains(&Language::DE),
+            en: languages.contains(&Language::EN),
+            fr: languages.contains(&Language::FR),
+            it: languages.contains(&Language::IT),
+            es: languages.contains(&Language::ES),
+            codes: languages.iter().map(|language| language.to_iso_code().to_string()).collect(),
+        }
+    }
+}
+
+impl From<Language> for PreparedLanguage{
+    fn from(language: Language) -> Self{
+        PreparedLanguage::from(&[language][..])
+    }
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Eq, Hash)]
 pub struct PreparedContentBlock{
     pub id: String,
     pub block_type: BlockType,
     pub html: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone, PartialEq)]
+impl PreparedContentBlock{
+    /// Visible text of the block: tags, comments and attribute values are removed and entities are decoded
+    /// Block level tags (e.g. `<p>` or `<li>`) separate words, inline tags (e.g. `<em>`) don't.
+    pub fn plain_text(&self) -> String{
+        html_to_text(&self.html)
+    }
+}
+
+/// Tags whose start or end separates words, other tags are treated as inline
+const BLOCK_LEVEL_TAGS: &[&str] = &["address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption", "figure", "footer",
+    "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul"];
+
+/// Strips the markup from html, see [PreparedContentBlock::plain_text]
+fn html_to_text(html: &str) -> String{
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find(['<', '&']){
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--"){
+            rest = comment.split_once("-->").map(|(_, after)| after).unwrap_or("");
+        }else if rest.starts_with('<'){
+            let end = tag_end(rest);
+            let name : String = rest[1..end].trim_start_matches('/').chars().take_while(char::is_ascii_alphanumeric).collect();
+            if BLOCK_LEVEL_TAGS.iter().any(|tag| tag.eq_ignore_ascii_case(&name)){
+                text.push(' ');
+            }
+            rest = rest.get(end + 1..).unwrap_or("");
+        }else{
+            match decode_entity(rest){
+                Some((c, len)) => {
+                    text.push(c);
+                    rest = &rest[len..];
+                },
+                None => {
+                    text.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+    }
+    text.push_str(rest);
+
+    text
+}
+
+/// Index of the ">" closing the tag at the start of `html`, skipping quoted attribute values. Length of `html` if unterminated.
+fn tag_end(html: &str) -> usize{
+    let mut quote = None;
+    for (i, c) in html.char_indices().skip(1){
+        match (quote, c){
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return i,
+            _ => {},
+        }
+    }
+    html.len()
+}
+
+/// Decodes the entity at the start of `html` (e.g. "&amp;" or "&#8211;"), returns the character and the length of the entity
+/// Unknown named entities are decoded to U+FFFD, so they still count as a character.
+fn decode_entity(html: &str) -> Option<(char, usize)>{
+    let end = html.find(';').filter(|end| *end <= 32)?;
+    let entity = &html[1..end];
+
+    let c = if let Some(number) = entity.strip_prefix('#'){
+        let code = match number.strip_prefix(['x', 'X']){
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => number.parse().ok(),
+        };
+        char::from_u32(code?)?
+    }else if !entity.is_empty() && entity.chars().all(|c| c.is_ascii_alphanumeric()){
+        match entity{
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            "nbsp" => ' ',
+            _ => '\u{FFFD}',
+        }
+    }else{
+        return None
+    };
+
+    Some((c, end + 1))
+}
+
+#[derive(Debug, Serialize, Deserialize, Encode, Decode, Clone, PartialEq, Eq, Hash)]
 pub enum BlockType{
     Paragraph,
     Heading,
     Raw,
     List,
     Quote,
-    Image
-}
\ No newline at end of file
+    Image,
+    // Variants below were added later and must stay at the end to keep the encoding of the ones above
+    Table,
+    /// Fenced code block
+    Code,
+    Math,
+    /// Inline footnote marker
+    Footnote,
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn settings_migrate_from_v2_to_v6(){
+        let v2 = ProjectSettingsV2{
+            toc_enabled: true,
+            csl_style: Some("chicago-author-date".to_string()),
+        };
+
+        let v3 : ProjectSettingsV3 = v2.into();
+        let v4 : ProjectSettingsV4 = v3.into();
+        let v5 : ProjectSettingsV5 = v4.into();
+        let v6 : ProjectSettingsV6 = v5.into();
+        assert_eq!(v6, ProjectSettingsV6{
+            toc_enabled: true,
+            csl_style: Some("chicago-author-date".to_string()),
+            csl_language_code: None,
+            metadata_page_additional_html: None,
+            cover_image_path: None,
+            backcover_image_path: None,
+            running_headers: None,
+            embed_fonts: true,
+            custom_fonts_path: None,
+        });
+    }
+
+    #[test]
+    fn settings_of_old_peers_are_v5(){
+        let v5 = ProjectSettingsV5{
+            toc_enabled: true,
+            csl_style: Some("chicago-author-date".to_string()),
+            csl_language_code: None,
+            metadata_page_additional_html: None,
+            cover_image_path: None,
+            backcover_image_path: None,
+            running_headers: None,
+        };
+        let project = PreparedProject{
+            metadata: PreparedMetadataBuilder::new("Book").build(),
+            settings: Some(ProjectSettingsV6{
+                embed_fonts: false,
+                custom_fonts_path: Some("fonts".to_string()),
+                ..v5.clone().into()
+            }),
+            sections: Vec::new(),
+        };
+        let old = (&project.metadata, Some(&v5), &project.sections);
+        let bytes = crate::with_protocol_version(5, || bincode::encode_to_vec(old, bincode::config::standard())).unwrap();
+        assert_eq!(crate::with_protocol_version(5, || bincode::encode_to_vec(&project, bincode::config::standard())).unwrap(), bytes);
+
+        let (decoded, _) : (PreparedProject, _) = crate::with_protocol_version(5, || bincode::decode_from_slice(&bytes, bincode::config::standard())).unwrap();
+        assert_eq!(decoded.settings, Some(v5.into()));
+        assert!(decoded.metadata == project.metadata);
+    }
+
+    #[test]
+    fn language_of_old_peers_only_has_german_and_english(){
+        let old = PreparedLanguageV1{
+            de: false,
+            en: true,
+        };
+        let bytes = bincode::encode_to_vec(&old, bincode::config::standard()).unwrap();
+
+        let (decoded, _) : (PreparedLanguage, _) = crate::with_protocol_version(6, || bincode::decode_from_slice(&bytes, bincode::config::standard())).unwrap();
+        assert_eq!(decoded.languages(), vec![Language::EN]);
+
+        let language = PreparedLanguage::from(&[Language::EN, Language::FR][..]);
+        assert_eq!(crate::with_protocol_version(6, || bincode::encode_to_vec(&language, bincode::config::standard())).unwrap(), bytes);
+        let current = bincode::encode_to_vec(&language, bincode::config::standard()).unwrap();
+        let (decoded, _) : (PreparedLanguage, _) = bincode::decode_from_slice(&current, bincode::config::standard()).unwrap();
+        assert_eq!(decoded.languages(), vec![Language::EN, Language::FR]);
+    }
+
+    #[cfg(feature = "crossref")]
+    #[test]
+    fn crossref_doi_data_needs_web_url(){
+        let doi = Identifier::new(IdentifierType::DOI, "10.17176/20240101-123456-0".to_string(), None);
+        let without_url = PreparedMetadataBuilder::new("Book").identifier(doi.clone()).build().to_crossref_xml();
+        assert!(!without_url.contains("doi_data"), "{}", without_url);
+
+        let with_url = PreparedMetadataBuilder::new("Book").identifier(doi).web_url("https://verfassungsblog.de/books/book").build().to_crossref_xml();
+        assert!(with_url.contains("<doi_data>\n      <doi>10.17176/20240101-123456-0</doi>\n      <resource>https://verfassungsblog.de/books/book</resource>\n    </doi_data>"), "{}", with_url);
+    }
+
+    #[cfg(feature = "crossref")]
+    #[test]
+    fn crossref_contains_title_translations(){
+        let xml = PreparedMetadataBuilder::new("Verfassungsrecht")
+            .subtitle("Eine Einführung")
+            .language(Language::DE)
+            .title_translation(Language::EN, "Constitutional Law", Some("An Introduction".to_string()))
+            .build()
+            .to_crossref_xml();
+
+        assert!(xml.contains("<titles>\n      <title>Verfassungsrecht</title>\n      <subtitle>Eine Einführung</subtitle>\n      \
+            <original_language_title language=\"en\">Constitutional Law</original_language_title>\n      <subtitle>An Introduction</subtitle>\n    </titles>"), "{}", xml);
+    }
+
+    #[test]
+    fn title_translations_are_only_sent_to_new_peers(){
+        let metadata = PreparedMetadataBuilder::new("Verfassungsrecht")
+            .title_translation(Language::EN, "Constitutional Law", None)
+            .build();
+
+        let old = crate::with_protocol_version(22, || bincode::encode_to_vec(&metadata, bincode::config::standard())).unwrap();
+        let (decoded, _) : (PreparedMetadata, _) = crate::with_protocol_version(22, || bincode::decode_from_slice(&old, bincode::config::standard())).unwrap();
+        assert!(decoded.title_translations.is_empty());
+        assert_eq!(decoded.title, "Verfassungsrecht");
+
+        let current = bincode::encode_to_vec(&metadata, bincode::config::standard()).unwrap();
+        let (decoded, _) : (PreparedMetadata, _) = bincode::decode_from_slice(&current, bincode::config::standard()).unwrap();
+        assert_eq!(decoded.title_translations, metadata.title_translations);
+    }
+
+    #[test]
+    fn identifier_values_are_normalized(){
+        let doi = |value: &str| Identifier::new(IdentifierType::DOI, value.to_string(), None).value;
+        assert_eq!(doi("10.1234/foo"), "10.1234/foo");
+        assert_eq!(doi("doi:10.1234/foo"), "10.1234/foo");
+        assert_eq!(doi("https://doi.org/10.1234/foo/"), "10.1234/foo");
+        assert_eq!(doi("http://dx.doi.org/10.1234/foo"), "10.1234/foo");
+
+        let url = |value: &str| Identifier::new(IdentifierType::URL, value.to_string(), None).value;
+        assert_eq!(url("verfassungsblog.de/books"), "https://verfassungsblog.de/books");
+        assert_eq!(url(" HTTPS://Verfassungsblog.DE/ "), "https://verfassungsblog.de");
+        assert_eq!(url("http://example.org/Path?Query"), "http://example.org/Path?Query");
+        assert_eq!(url(""), "");
+        assert_eq!(url("   "), "   ");
+    }
+
+    fn validate(identifier_type: IdentifierType, value: &str) -> Result<(), IdentifierError>{
+        Identifier::new(identifier_type, value.to_string(), None).validate()
+    }
+
+    #[test]
+    fn isbn_and_issn_check_digits(){
+        assert_eq!(validate(IdentifierType::ISBN, "0-306-40615-2"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISBN, "0306406152"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISBN, "0-8044-2957-X"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISBN, "0-8044-2957-x"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISBN, "978-3-16-148410-0"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISBN, "978 3 16 148410 0"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISBN, "0-306-40615-3"), Err(IdentifierError::InvalidChecksum("0-306-40615-3".to_string())));
+        assert_eq!(validate(IdentifierType::ISBN, "978-3-16-148410-1"), Err(IdentifierError::InvalidChecksum("978-3-16-148410-1".to_string())));
+        assert!(validate(IdentifierType::ISBN, "978-3-16-148410-X").is_err());
+        assert_eq!(validate(IdentifierType::ISBN, "978-3-16"), Err(IdentifierError::InvalidIsbn("978-3-16".to_string())));
+
+        assert_eq!(validate(IdentifierType::ISSN, "0317-8471"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISSN, "2434-561X"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISSN, "2434561x"), Ok(()));
+        assert_eq!(validate(IdentifierType::ISSN, "0317-8472"), Err(IdentifierError::InvalidChecksum("0317-8472".to_string())));
+        assert!(matches!(validate(IdentifierType::ISSN, "0317-847"), Err(IdentifierError::InvalidFormat(IdentifierType::ISSN, _))));
+    }
+
+    #[test]
+    fn orcid_check_digits(){
+        assert_eq!(Identifier::normalize_orcid("0000-0002-1825-0097"), Ok("0000-0002-1825-0097".to_string()));
+        assert_eq!(Identifier::normalize_orcid("https://orcid.org/0000-0002-1825-0097"), Ok("0000-0002-1825-0097".to_string()));
+        assert_eq!(Identifier::normalize_orcid("0000000218250097"), Ok("0000-0002-1825-0097".to_string()));
+        assert_eq!(Identifier::normalize_orcid("0000-0002-1694-233x"), Ok("0000-0002-1694-233X".to_string()));
+        assert_eq!(Identifier::normalize_orcid("0000-0002-1825-0098"), Err(IdentifierError::InvalidChecksum("0000-0002-1825-0098".to_string())));
+        assert!(matches!(Identifier::normalize_orcid("0000-0002-1825"), Err(IdentifierError::InvalidFormat(IdentifierType::ORCID, _))));
+        assert_eq!(validate(IdentifierType::ORCID, "https://orcid.org/0000-0002-1694-233X"), Ok(()));
+    }
+
+    #[test]
+    fn licenses_round_trip_through_spdx(){
+        let licenses = [License::CC0, License::CC_BY_4, License::CC_BY_SA_4, License::CC_BY_ND_4, License::CC_BY_NC_4,
+            License::CC_BY_NC_SA_4, License::CC_BY_NC_ND_4, License::Other("MIT".to_string())];
+        for license in licenses{
+            assert_eq!(license.to_spdx().parse::<License>(), Ok(license.clone()), "{}", license.to_spdx());
+        }
+
+        assert_eq!("cc-by-sa-4.0".parse::<License>(), Ok(License::CC_BY_SA_4));
+        assert_eq!(" CC0-1.0 ".parse::<License>(), Ok(License::CC0));
+        assert_eq!("CC-BY-3.0".parse::<License>(), Ok(License::Other("CC-BY-3.0".to_string())));
+        assert_eq!(License::Other("CC-BY-3.0".to_string()).to_spdx(), "CC-BY-3.0");
+    }
+
+    fn person(first_names: &str, last_names: &str) -> Person{
+        Person{
+            id: Some(uuid::Uuid::new_v4()),
+            first_names: Some(first_names.to_string()),
+            last_names: last_names.to_string(),
+            orcid: None,
+            gnd: None,
+            bios: None,
+            ror: None,
+            name_prefix: None,
+            name_suffix: None,
+            particle: None,
+        }
+    }
+
+    /// Metadata with most fields set, for the export formats
+    fn book_metadata() -> PreparedMetadata{
+        PreparedMetadataBuilder::new("Verfassungsrecht")
+            .subtitle("Eine Einführung")
+            .author(Person{
+                particle: Some("von".to_string()),
+                ..person("Armin", "Bogdandy")
+            })
+            .editor(person("Maximilian", "Steinbeis"))
+            .published("2024-03-05")
+            .publisher("Verfassungsbooks")
+            .language(Language::DE)
+            .identifier(Identifier::new(IdentifierType::DOI, "https://doi.org/10.17176/20240305-1".to_string(), None))
+            .identifier(Identifier::new(IdentifierType::ISBN, "978-3-16-148410-0".to_string(), None))
+            .web_url("https://verfassungsblog.de/books/verfassungsrecht")
+            .build()
+    }
+
+    #[test]
+    fn bibtex_of_book(){
+        assert_eq!(book_metadata().to_bibtex("bogdandy2024"), "@book{bogdandy2024,
+  title = {Verfassungsrecht: Eine Einführung},
+  author = {Bogdandy, Armin von},
+  editor = {Steinbeis, Maximilian},
+  year = {2024},
+  publisher = {Verfassungsbooks},
+  isbn = {978-3-16-148410-0},
+  doi = {10.17176/20240305-1},
+  url = {https://verfassungsblog.de/books/verfassungsrecht},
+}
+");
+
+        let escaped = PreparedMetadataBuilder::new("Law & Order: 100% {Braces}").build().to_bibtex("key");
+        assert_eq!(escaped, "@book{key,\n  title = {Law \\& Order: 100\\% \\{Braces\\}},\n}\n");
+    }
+
+    #[cfg(feature = "csl")]
+    #[test]
+    fn csl_json_of_book(){
+        assert_eq!(book_metadata().to_csl_json(), serde_json::json!({
+            "id": "10.17176/20240305-1",
+            "type": "book",
+            "title": "Verfassungsrecht: Eine Einführung",
+            "author": [{"family": "Bogdandy", "given": "Armin", "dropping-particle": "von"}],
+            "editor": [{"family": "Steinbeis", "given": "Maximilian"}],
+            "issued": {"date-parts": [[2024, 3, 5]]},
+            "publisher": "Verfassungsbooks",
+            "language": "de",
+            "DOI": "10.17176/20240305-1",
+            "ISBN": "978-3-16-148410-0",
+            "URL": "https://verfassungsblog.de/books/verfassungsrecht",
+        }));
+    }
+
+    #[cfg(feature = "dublin-core")]
+    #[test]
+    fn dublin_core_of_book(){
+        let mut metadata = book_metadata();
+        metadata.license = Some(License::CC_BY_4.into());
+        metadata.keywords = Some(vec![Keyword{
+            title: "Verfassung".to_string(),
+            gnd: None,
+        }]);
+        assert_eq!(metadata.to_dublin_core_xml(), r#"<?xml version="1.0" encoding="UTF-8"?>
+<oai_dc:dc xmlns:oai_dc="http://www.openarchives.org/OAI/2.0/oai_dc/" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <dc:title>Verfassungsrecht: Eine Einführung</dc:title>
+  <dc:creator>Bogdandy, Armin von</dc:creator>
+  <dc:contributor>Steinbeis, Maximilian</dc:contributor>
+  <dc:language>de</dc:language>
+  <dc:publisher>Verfassungsbooks</dc:publisher>
+  <dc:date>2024-03-05</dc:date>
+  <dc:rights>Creative Commons Attribution 4.0 International (CC BY 4.0)</dc:rights>
+  <dc:subject>Verfassung</dc:subject>
+  <dc:identifier>https://doi.org/10.17176/20240305-1</dc:identifier>
+  <dc:identifier>urn:isbn:978-3-16-148410-0</dc:identifier>
+</oai_dc:dc>"#);
+    }
+
+    #[cfg(feature = "jats")]
+    #[test]
+    fn jats_round_trip(){
+        let section = PreparedSectionMetadata{
+            title: "Grundrechte & Demokratie".to_string(),
+            subtitle: Some("Ein Überblick".to_string()),
+            toc_title: Some("Grundrechte".to_string()),
+            authors: vec![Person{
+                particle: Some("von".to_string()),
+                name_prefix: Some("Prof. Dr.".to_string()),
+                orcid: Some(Identifier::new(IdentifierType::ORCID, "0000-0002-1825-0097".to_string(), None)),
+                ..person("Armin", "Bogdandy")
+            }],
+            editors: vec![person("Maximilian", "Steinbeis")],
+            web_url: Some("https://verfassungsblog.de/grundrechte?lang=de&print=1".to_string()),
+            identifiers: vec![
+                Identifier::new(IdentifierType::DOI, "10.17176/20240305-2".to_string(), None),
+                Identifier::new(IdentifierType::URL, "https://verfassungsblog.de/grundrechte".to_string(), None),
+            ],
+            published: Some("2024-03-05".to_string()),
+            lang: PreparedLanguage::from(Language::DE),
+        };
+
+        let read = PreparedSectionMetadata::from_jats_xml(&section.to_jats_xml()).unwrap();
+        assert_eq!(read.title, section.title);
+        assert_eq!(read.subtitle, section.subtitle);
+        assert_eq!(read.toc_title, section.toc_title);
+        assert_eq!(read.web_url, section.web_url);
+        assert_eq!(read.published, section.published);
+        assert_eq!(read.lang.languages(), vec![Language::DE]);
+
+        let identifiers : Vec<(&IdentifierType, &str)> = read.identifiers.iter().map(|identifier| (&identifier.identifier_type, identifier.value.as_str())).collect();
+        assert_eq!(identifiers, vec![(&IdentifierType::DOI, "10.17176/20240305-2"), (&IdentifierType::URL, "https://verfassungsblog.de/grundrechte")]);
+
+        let names = |persons: &[Person]| persons.iter().map(|person| person.display_name(NameOrder::FirstLast)).collect::<Vec<String>>();
+        assert_eq!(names(&read.authors), names(&section.authors));
+        assert_eq!(names(&read.editors), names(&section.editors));
+        assert_eq!(read.authors[0].orcid.as_ref().map(|orcid| Identifier::normalize_orcid(&orcid.value)), Some(Ok("0000-0002-1825-0097".to_string())));
+    }
+
+    fn section(title: &str, sub_sections: Vec<PreparedSection>) -> PreparedSection{
+        PreparedSection{
+            id: uuid::Uuid::new_v4(),
+            sub_sections,
+            children: Vec::new(),
+            metadata: PreparedSectionMetadata{
+                title: title.to_string(),
+                subtitle: None,
+                toc_title: None,
+                authors: Vec::new(),
+                editors: Vec::new(),
+                web_url: None,
+                identifiers: Vec::new(),
+                published: None,
+                lang: PreparedLanguage::from(&[][..]),
+            },
+            visible_in_toc: true,
+            endnotes: Vec::new(),
+        }
+    }
+
+    fn project(sections: Vec<PreparedSection>) -> PreparedProject{
+        PreparedProject{
+            metadata: PreparedMetadataBuilder::new("Book").build(),
+            settings: None,
+            sections,
+        }
+    }
+
+    #[test]
+    fn toc_of_nested_sections(){
+        let mut hidden = section("Impressum", vec![section("Lizenz", Vec::new())]);
+        hidden.visible_in_toc = false;
+        let mut chapter = section("Erstes Kapitel: Die Grundlagen des Verfassungsrechts", vec![
+            section("1.1 Begriff", vec![section("1.1.1 Geschichte", Vec::new())]),
+            section("1.2 Funktionen", Vec::new()),
+        ]);
+        chapter.metadata.toc_title = Some("Grundlagen".to_string());
+        let project = project(vec![hidden, chapter, section("Literatur", Vec::new())]);
+
+        fn flatten(entries: &[TocEntry], flat: &mut Vec<(u32, String)>){
+            for entry in entries{
+                flat.push((entry.level, entry.title.clone()));
+                flatten(&entry.children, flat);
+            }
+        }
+        let toc = project.build_toc();
+        let mut flat = Vec::new();
+        flatten(&toc, &mut flat);
+        assert_eq!(flat, vec![
+            (1, "Grundlagen".to_string()),
+            (2, "1.1 Begriff".to_string()),
+            (3, "1.1.1 Geschichte".to_string()),
+            (2, "1.2 Funktionen".to_string()),
+            (1, "Literatur".to_string()),
+        ]);
+        assert_eq!(toc[0].id, project.sections[1].id);
+        assert_eq!(toc[0].children[0].children[0].id, project.sections[1].sub_sections[0].sub_sections[0].id);
+    }
+
+    #[test]
+    fn display_name_with_particle(){
+        let bogdandy = Person{
+            particle: Some("von".to_string()),
+            name_prefix: Some("Prof. Dr.".to_string()),
+            ..person("Armin", "Bogdandy")
+        };
+        assert_eq!(bogdandy.display_name(NameOrder::FirstLast), "Prof. Dr. Armin von Bogdandy");
+        assert_eq!(bogdandy.display_name(NameOrder::LastFirst), "Bogdandy, Armin von");
+        assert_eq!(bogdandy.sort_key(), "bogdandy, armin");
+
+        let suffix = Person{
+            name_suffix: Some("Jr.".to_string()),
+            first_names: Some(" ".to_string()),
+            ..person("", "King")
+        };
+        assert_eq!(suffix.display_name(NameOrder::FirstLast), "King Jr.");
+        assert_eq!(suffix.display_name(NameOrder::LastFirst), "King, Jr.");
+        assert_eq!(suffix.sort_key(), "king");
+
+        let mut persons = [person("Maximilian", "Steinbeis"), bogdandy, person("Anna", "Becker")];
+        persons.sort_by_key(Person::sort_key);
+        let last_names : Vec<&str> = persons.iter().map(|person| person.last_names.as_str()).collect();
+        assert_eq!(last_names, vec!["Becker", "Bogdandy", "Steinbeis"]);
+    }
+
+    #[test]
+    fn contributors_are_merged_by_orcid_or_name(){
+        let orcid = |value: &str| Some(Identifier::new(IdentifierType::ORCID, value.to_string(), None));
+        let bogdandy = Person{
+            orcid: orcid("0000-0002-1825-0097"),
+            ..person("Armin", "Bogdandy")
+        };
+        let bogdandy_in_section = Person{
+            orcid: orcid("https://orcid.org/0000000218250097"),
+            name_prefix: Some("Prof. Dr.".to_string()),
+            ..person("A.", "Bogdandy")
+        };
+        let steinbeis = person("Maximilian", "Steinbeis");
+        let steinbeis_in_sub_section = Person{
+            bios: Some(vec![Biography{
+                content: "Gründer des Verfassungsblogs".to_string(),
+                lang: Some(Language::DE),
+            }]),
+            ..person(" Maximilian ", "Steinbeis")
+        };
+        // Same name, but the ORCID shows it's someone else
+        let other_steinbeis = Person{
+            orcid: orcid("0000-0002-1694-233X"),
+            ..person("Maximilian", "Steinbeis")
+        };
+
+        let mut chapter = section("Kapitel", vec![section("Abschnitt", Vec::new())]);
+        chapter.metadata.authors = vec![bogdandy_in_section, other_steinbeis];
+        chapter.sub_sections[0].metadata.editors = vec![steinbeis_in_sub_section];
+        let mut project = project(vec![chapter]);
+        project.metadata.authors = vec![bogdandy.clone()];
+        project.metadata.editors = vec![steinbeis.clone()];
+
+        let contributors = project.unique_contributors();
+        assert_eq!(contributors.len(), 3);
+        assert_eq!(contributors[0].first_names.as_deref(), Some("Armin"));
+        assert_eq!(contributors[0].orcid, bogdandy.orcid);
+        assert_eq!(contributors[0].name_prefix.as_deref(), Some("Prof. Dr."));
+        assert_eq!(contributors[1].id, steinbeis.id);
+        assert_eq!(contributors[1].bios.as_ref().map(Vec::len), Some(1));
+        assert_eq!(contributors[2].orcid.as_ref().map(|orcid| orcid.value.as_str()), Some("0000-0002-1694-233X"));
+    }
+
+    fn with_endnotes(mut section: PreparedSection, numbers: &[usize]) -> PreparedSection{
+        section.endnotes = numbers.iter().map(|num| PreparedEndnote{
+            num: *num,
+            id: uuid::Uuid::new_v4(),
+            content: format!("Endnote {}", num),
+        }).collect();
+        section
+    }
+
+    #[test]
+    fn endnotes_are_numbered_across_nested_sections(){
+        let chapter = with_endnotes(section("Kapitel 1", vec![with_endnotes(section("1.1", Vec::new()), &[1, 2])]), &[1, 2]);
+        let mut project = project(vec![chapter, with_endnotes(section("Kapitel 2", Vec::new()), &[5])]);
+        let numbers = |project: &PreparedProject| project.sections_iter().map(|section| section.endnotes.iter().map(|endnote| endnote.num).collect()).collect::<Vec<Vec<usize>>>();
+
+        let mapping = project.renumber_endnotes(EndnoteNumbering::ContinuousAcrossBook);
+        assert_eq!(numbers(&project), vec![vec![1, 2], vec![3, 4], vec![5]]);
+        assert_eq!(mapping[&project.sections[0].sub_sections[0].id], HashMap::from([(1, 3), (2, 4)]));
+        assert_eq!(mapping[&project.sections[1].id], HashMap::from([(5, 5)]));
+        assert_eq!(project.sections[0].sub_sections[0].endnotes[0].content, "Endnote 1");
+
+        let mapping = project.renumber_endnotes(EndnoteNumbering::RestartPerSection);
+        assert_eq!(numbers(&project), vec![vec![1, 2], vec![1, 2], vec![1]]);
+        assert_eq!(mapping[&project.sections[0].sub_sections[0].id], HashMap::from([(3, 1), (4, 2)]));
+    }
+
+    #[test]
+    fn sections_are_moved_without_cycles(){
+        let mut project = project(vec![
+            section("Kapitel 1", vec![section("1.1", vec![section("1.1.1", Vec::new())])]),
+            section("Kapitel 2", Vec::new()),
+        ]);
+        let titles = |project: &PreparedProject| project.sections_iter().map(|section| section.metadata.title.clone()).collect::<Vec<String>>();
+        let chapter = project.sections[0].id;
+        let sub_section = project.sections[0].sub_sections[0].id;
+        let sub_sub_section = project.sections[0].sub_sections[0].sub_sections[0].id;
+        let unknown = uuid::Uuid::new_v4();
+        let before = titles(&project);
+
+        assert_eq!(project.move_section(chapter, Some(chapter), 0), Err(MoveError::TargetIsDescendant(chapter)));
+        assert_eq!(project.move_section(chapter, Some(sub_sub_section), 0), Err(MoveError::TargetIsDescendant(sub_sub_section)));
+        assert_eq!(project.move_section(unknown, None, 0), Err(MoveError::SectionNotFound(unknown)));
+        assert_eq!(project.move_section(sub_section, Some(unknown), 0), Err(MoveError::ParentNotFound(unknown)));
+        assert_eq!(project.move_section(sub_section, None, 3), Err(MoveError::IndexOutOfBounds{index: 3, len: 2}));
+        assert_eq!(titles(&project), before);
+
+        project.move_section(sub_section, Some(project.sections[1].id), 0).unwrap();
+        assert_eq!(titles(&project), vec!["Kapitel 1", "Kapitel 2", "1.1", "1.1.1"]);
+        project.move_section(chapter, None, 1).unwrap();
+        assert_eq!(titles(&project), vec!["Kapitel 2", "1.1", "1.1.1", "Kapitel 1"]);
+    }
+
+    fn with_blocks(mut section: PreparedSection, blocks: &[(BlockType, &str)]) -> PreparedSection{
+        section.children = blocks.iter().enumerate().map(|(i, (block_type, html))| PreparedContentBlock{
+            id: format!("{}-{}", section.metadata.title, i),
+            block_type: block_type.clone(),
+            html: html.to_string(),
+        }).collect();
+        section
+    }
+
+    #[test]
+    fn sections_and_blocks_in_document_order(){
+        assert_eq!(project(Vec::new()).sections_iter().count(), 0);
+        let project = project(vec![
+            with_blocks(section("1", vec![
+                with_blocks(section("1.1", vec![section("1.1.1", Vec::new())]), &[(BlockType::Paragraph, "c")]),
+                section("1.2", Vec::new()),
+            ]), &[(BlockType::Heading, "a"), (BlockType::Paragraph, "b")]),
+            with_blocks(section("2", Vec::new()), &[(BlockType::Paragraph, "d")]),
+        ]);
+
+        let titles : Vec<&str> = project.sections_iter().map(|section| section.metadata.title.as_str()).collect();
+        assert_eq!(titles, vec!["1", "1.1", "1.1.1", "1.2", "2"]);
+        let blocks : Vec<&str> = project.content_blocks().map(|block| block.html.as_str()).collect();
+        assert_eq!(blocks, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn replace_in_blocks_changes_text_and_markup(){
+        let mut project = project(vec![
+            with_blocks(section("1", vec![
+                with_blocks(section("1.1", Vec::new()), &[(BlockType::Paragraph, "<p>Bogdandi und Bogdandi</p>")]),
+            ]), &[
+                (BlockType::Paragraph, r#"<p><a href="https://example.org/Bogdandi">Armin von Bogdandi</a></p>"#),
+                (BlockType::Quote, "<blockquote>Bogdandi</blockquote>"),
+            ]),
+        ]);
+
+        assert_eq!(project.replace_in_blocks("Bogdandi", "Bogdandy", &[BlockType::Paragraph]), 4);
+        let blocks : Vec<&str> = project.content_blocks().map(|block| block.html.as_str()).collect();
+        assert_eq!(blocks, vec![
+            r#"<p><a href="https://example.org/Bogdandy">Armin von Bogdandy</a></p>"#,
+            "<blockquote>Bogdandi</blockquote>",
+            "<p>Bogdandy und Bogdandy</p>",
+        ]);
+
+        assert_eq!(project.replace_in_blocks("blockquote", "q", &[]), 2);
+        assert_eq!(project.content_blocks().nth(1).unwrap().html, "<q>Bogdandi</q>");
+        assert_eq!(project.replace_in_blocks("", "x", &[]), 0);
+    }
+
+    #[test]
+    fn words_and_characters_of_html(){
+        let mut chapter = with_blocks(section("1", vec![
+            with_blocks(section("1.1", Vec::new()), &[(BlockType::Paragraph, "<p>Noch ein Satz.</p>")]),
+        ]), &[
+            (BlockType::Paragraph, r#"<p>Das <em>Grund</em>gesetz &amp; die <a href="x y z">Verfassung</a> – heute</p><!-- Kommentar -->"#),
+            (BlockType::List, "<ul><li>eins</li><li>zwei</li></ul>"),
+        ]);
+        chapter.endnotes = vec![PreparedEndnote{
+            num: 1,
+            id: uuid::Uuid::new_v4(),
+            content: "<p>Siehe BVerfGE 1, 14.</p>".to_string(),
+        }];
+        let project = project(vec![chapter]);
+
+        assert_eq!(project.sections[0].word_count(), 11);
+        assert_eq!(project.sections[0].char_count(), 40 + 9 + 20);
+        assert_eq!(project.word_count(), 14);
+        assert_eq!(project.char_count(), 40 + 9 + 20 + 14);
+        assert_eq!(project.word_counts_per_section(), vec![(project.sections[0].id, 11), (project.sections[0].sub_sections[0].id, 3)]);
+    }
+
+    #[test]
+    fn duplicate_ids_in_nested_sections(){
+        let first = with_endnotes(section("Kapitel 1", vec![section("1.1", vec![section("1.1.1", Vec::new())])]), &[1]);
+        let second = with_endnotes(section("Kapitel 2", vec![with_endnotes(section("2.1", Vec::new()), &[2])]), &[1]);
+        let mut project = project(vec![first, second]);
+        assert_eq!(project.check_id_uniqueness(), Ok(()));
+
+        let chapter_id = project.sections[0].id;
+        let sub_section_id = project.sections[0].sub_sections[0].id;
+        project.sections[0].sub_sections[0].sub_sections[0].id = chapter_id;
+        project.sections[1].endnotes[0].id = sub_section_id;
+        project.sections[1].sub_sections[0].endnotes[0].id = sub_section_id;
+
+        assert_eq!(project.check_id_uniqueness(), Err(vec![chapter_id, sub_section_id]));
+    }
+
+    #[test]
+    fn bio_fallback_order(){
+        let bio = |content: &str, lang: Option<Language>| Biography{
+            content: content.to_string(),
+            lang,
+        };
+        let mut person = person("Maximilian", "Steinbeis");
+        assert_eq!(person.bio_for(&Language::DE), None);
+
+        person.bios = Some(vec![bio("Editor", Some(Language::EN)), bio("Rédacteur", Some(Language::FR))]);
+        assert_eq!(person.bio_for(&Language::DE).map(|bio| bio.content.as_str()), Some("Editor"));
+
+        person.bios.as_mut().unwrap().push(bio("Ohne Sprache", None));
+        person.bios.as_mut().unwrap().push(bio("Auch ohne Sprache", None));
+        assert_eq!(person.bio_for(&Language::DE).map(|bio| bio.content.as_str()), Some("Ohne Sprache"));
+        assert_eq!(person.bio_for(&Language::FR).map(|bio| bio.content.as_str()), Some("Rédacteur"));
+
+        person.bios.as_mut().unwrap().push(bio("Herausgeber", Some(Language::DE)));
+        person.bios.as_mut().unwrap().push(bio("Chefredakteur", Some(Language::DE)));
+        assert_eq!(person.bio_for(&Language::DE).map(|bio| bio.content.as_str()), Some("Herausgeber"));
+    }
+
+    #[test]
+    fn dedupe_keywords_prefers_gnd(){
+        let keyword = |title: &str, gnd: Option<&str>| Keyword{
+            title: title.to_string(),
+            gnd: gnd.map(|gnd| Identifier::new(IdentifierType::GND, gnd.to_string(), None)),
+        };
+        let mut metadata = PreparedMetadataBuilder::new("Book").keywords(vec![
+            keyword("Grundrechte", None),
+            keyword("Verfassung", Some("4062801-2")),
+            keyword(" grundrechte ", Some("4072090-1")),
+            keyword("GRUNDRECHTE", None),
+            keyword("Bank", Some("4004436-1")),
+            keyword("Bank", Some("4144137-0")),
+            keyword("bank", Some("4144137-0")),
+        ]).build();
+        metadata.dedupe_keywords();
+
+        let keywords : Vec<(&str, Option<&str>)> = metadata.keywords.iter().flatten()
+            .map(|keyword| (keyword.title.as_str(), keyword.gnd.as_ref().map(|gnd| gnd.value.as_str()))).collect();
+        assert_eq!(keywords, vec![
+            (" grundrechte ", Some("4072090-1")),
+            ("Verfassung", Some("4062801-2")),
+            ("Bank", Some("4004436-1")),
+            ("Bank", Some("4144137-0")),
+        ]);
+    }
+
+    #[test]
+    fn equal_projects_have_equal_hashes(){
+        use std::hash::{BuildHasher, RandomState};
+
+        let mut original = project(vec![with_blocks(section("Kapitel 1", vec![section("1.1", Vec::new())]), &[(BlockType::Paragraph, "<p>Text</p>")])]);
+        original.metadata = book_metadata();
+        let bytes = bincode::encode_to_vec(&original, bincode::config::standard()).unwrap();
+        let (mut copy, _) : (PreparedProject, _) = bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+
+        let hasher = RandomState::new();
+        assert!(original == copy);
+        assert_eq!(hasher.hash_one(&original), hasher.hash_one(&copy));
+
+        copy.sections[0].sub_sections[0].metadata.title = "1.2".to_string();
+        assert!(original != copy);
+        assert_ne!(hasher.hash_one(&original), hasher.hash_one(&copy));
+
+        // Identifiers with the same value differ in their random id
+        assert!(book_metadata() != book_metadata());
+    }
+
+    #[test]
+    fn block_types_keep_their_encoding(){
+        let block_types = [BlockType::Paragraph, BlockType::Heading, BlockType::Raw, BlockType::List, BlockType::Quote, BlockType::Image,
+            BlockType::Table, BlockType::Code, BlockType::Math, BlockType::Footnote];
+        for (discriminant, block_type) in block_types.into_iter().enumerate(){
+            let bytes = bincode::encode_to_vec(&block_type, bincode::config::standard()).unwrap();
+            assert_eq!(bytes, vec![discriminant as u8], "{:?}", block_type);
+            let (decoded, _) : (BlockType, _) = bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+            assert_eq!(decoded, block_type);
+        }
+    }
+
+    fn date(year: i32, month: Option<u8>, day: Option<u8>) -> Option<PartialDate>{
+        Some(PartialDate{
+            year,
+            month,
+            day,
+        })
+    }
+
+    #[test]
+    fn publication_date_formats(){
+        let cases = [
+            ("2024", date(2024, None, None)),
+            ("2024-03", date(2024, Some(3), None)),
+            ("2024-03-05", date(2024, Some(3), Some(5))),
+            (" 2024-03-05T10:00:00Z ", date(2024, Some(3), Some(5))),
+            ("2024-03-05 10:00", date(2024, Some(3), Some(5))),
+            ("2024/03/05", date(2024, Some(3), Some(5))),
+            ("03/2024", date(2024, Some(3), None)),
+            ("03.2024", date(2024, Some(3), None)),
+            ("05.03.2024", date(2024, Some(3), Some(5))),
+            ("March 2024", date(2024, Some(3), None)),
+            ("5 Mar 2024", date(2024, Some(3), Some(5))),
+            ("March 5, 2024", date(2024, Some(3), Some(5))),
+            ("5. März 2024", date(2024, Some(3), Some(5))),
+            ("DEZ 2023", date(2023, Some(12), None)),
+        ];
+
+        for (input, expected) in cases{
+            assert_eq!(parse_publication_date(input), expected, "{}", input);
+        }
+        assert_eq!(normalize_publication_date("5. März 2024").as_deref(), Some("2024-03-05"));
+        assert_eq!(normalize_publication_date("03/2024").as_deref(), Some("2024-03"));
+    }
+
+    #[test]
+    fn ambiguous_publication_dates_are_rejected(){
+        // Day and month are only told apart if one of them is larger than 12
+        assert_eq!(parse_publication_date("25/03/2024"), date(2024, Some(3), Some(25)));
+        assert_eq!(parse_publication_date("03/25/2024"), date(2024, Some(3), Some(25)));
+        assert_eq!(parse_publication_date("25-03-2024"), date(2024, Some(3), Some(25)));
+        assert_eq!(parse_publication_date("03/05/2024"), None);
+        assert_eq!(parse_publication_date("12/12/2024"), None);
+        assert_eq!(parse_publication_date("13/13/2024"), None);
+        // Dots always mean day.month.year
+        assert_eq!(parse_publication_date("03.05.2024"), date(2024, Some(5), Some(3)));
+    }
+
+    #[test]
+    fn invalid_publication_dates_are_rejected(){
+        for input in ["", "24", "24-03-05", "2024-13", "2024-00-10", "2024-03-00", "2024-04-31", "2023-02-29", "2024-03/05",
+            "5 2024", "Foo 2024", "March April 2024", "March 5 6 2024", "2024-03-05-01", "2024--05"]{
+            assert_eq!(parse_publication_date(input), None, "{}", input);
+        }
+
+        assert_eq!(parse_publication_date("2024-02-29"), date(2024, Some(2), Some(29)));
+        assert_eq!(parse_publication_date("1900-02-29"), None);
+        assert_eq!(parse_publication_date("2000-02-29"), date(2000, Some(2), Some(29)));
+    }
+}
@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use crate::{negotiate_version, read_message_with_config, send_message_with_config, Message, ResumeRequest, TransportConfig, TransportError};
+
+/// How often and how fast [connect_with_retry] tries to (re)connect
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy{
+    /// Maximum number of connection attempts before giving up
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt, doubled after each further one
+    pub initial_backoff: Duration,
+    /// Upper limit of the wait between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy{
+    fn default() -> Self {
+        ReconnectPolicy{
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ReconnectPolicy{
+    /// Wait after the given number of failed attempts (starting at 1)
+    pub fn backoff(&self, failed_attempts: u32) -> Duration{
+        let factor = 2u32.saturating_pow(failed_attempts.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Calls `connect` and exchanges the [Message::Hello] until it succeeds, waiting with exponential backoff in between
+///
+/// Only lost connections (see [TransportError::is_connection_lost]) are retried, e.g. an incompatible protocol version is returned right away.
+/// Returns the socket with the negotiated protocol version (see [TransportConfig::protocol_version]), or the last error after
+/// `policy.max_attempts` failed attempts.
+pub async fn connect_with_retry<S, F, Fut>(mut connect: F, policy: &ReconnectPolicy) -> Result<(S, u32), TransportError>
+    where S: AsyncRead + AsyncWrite + Unpin, F: FnMut() -> Fut, Fut: Future<Output = std::io::Result<S>>{
+    let mut last_error = TransportError::Timeout;
+
+    for attempt in 0..policy.max_attempts{
+        if attempt > 0{
+            tokio::time::sleep(policy.backoff(attempt)).await;
+        }
+
+        let result = match connect().await{
+            Ok(mut socket) => negotiate_version(&mut socket).await.map(|version| (socket, version)),
+            Err(e) => Err(e.into()),
+        };
+        match result{
+            Ok(connection) => return Ok(connection),
+            Err(e) if e.is_connection_lost() => last_error = e,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Reads the messages of a request from `socket` and passes them to `handle` until it returns a result
+///
+/// If the connection is lost, reconnects with [connect_with_retry] and sends a [Message::ResumeRequest] for `request_id`,
+/// so the rendering server continues with the status of the request. The attempts are counted per lost connection.
+/// `handle` may therefore see the current status twice. Returns the result of `handle` with the socket it was read from.
+/// The new connection uses `config` with the protocol version negotiated with the server it reconnected to.
+pub async fn receive_with_reconnect<S, F, Fut, H, T>(mut socket: S, mut connect: F, request_id: uuid::Uuid, policy: &ReconnectPolicy, config: &TransportConfig, mut handle: H) -> Result<(T, S), TransportError>
+    where S: AsyncRead + AsyncWrite + Unpin, F: FnMut() -> Fut, Fut: Future<Output = std::io::Result<S>>, H: FnMut(Message) -> Option<T>{
+    let mut config = config.clone();
+
+    loop {
+        match read_message_with_config(&mut socket, &config).await{
+            Ok((msg, _)) => {
+                if let Some(result) = handle(msg){
+                    return Ok((result, socket))
+                }
+            },
+            Err(e) if e.is_connection_lost() => {
+                let (new_socket, protocol_version) = connect_with_retry(&mut connect, policy).await?;
+                socket = new_socket;
+                config.protocol_version = protocol_version;
+                send_message_with_config(&mut socket, Message::ResumeRequest(ResumeRequest{request_id}), &config).await?;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
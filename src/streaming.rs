@@ -0,0 +1,387 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::io::SeekFrom;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use crate::{is_safe_name, read_message_with_config, send_message_with_config, CommunicationError, Message, NamedFile, RenderingResult, RenderingStatus, RenderingStatusUpdate, TransportConfig, TransportError};
+
+/// Maximum number of file bytes sent in one [ResultChunk] by [send_result_streamed]
+pub const RESULT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Part of a file of a [RenderingResult], see [send_result_streamed]
+#[derive(bincode::Decode, bincode::Encode, Debug, Clone)]
+pub struct ResultChunk{
+    /// id of the [crate::RenderingRequest] the result belongs to
+    #[bincode(with_serde)]
+    pub request_id: uuid::Uuid,
+    pub file_name: String,
+    /// Position of `data` in the file
+    pub offset: u64,
+    pub data: Vec<u8>,
+    /// Whether this is the last chunk of the file
+    pub last: bool,
+}
+
+/// Progress of receiving a streamed result, used to resume the transfer after the connection dropped
+///
+/// Updated by [receive_result_streamed_resumable] after every chunk which was written to disk. Can be persisted (e.g. as JSON)
+/// to resume after a restart as well. To resume, send it as [Message::ResumeResultTransfer] on the new connection,
+/// the sender continues with [send_result_streamed_resumed] and the receiver with [receive_result_streamed_resumable] with the same state.
+#[derive(bincode::Decode, bincode::Encode, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResultTransferState{
+    /// id of the [crate::RenderingRequest] the result belongs to
+    #[bincode(with_serde)]
+    pub request_id: uuid::Uuid,
+    /// Files received so far, in the order they were received
+    pub files: Vec<TransferredFile>,
+}
+
+/// Part of a [ResultTransferState]
+#[derive(bincode::Decode, bincode::Encode, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferredFile{
+    pub name: String,
+    /// Number of bytes already written to disk
+    pub received: u64,
+    /// Whether the last chunk of the file was received
+    pub complete: bool,
+}
+
+impl ResultTransferState{
+    /// State of a transfer which hasn't received anything yet
+    pub fn new(request_id: uuid::Uuid) -> Self{
+        ResultTransferState{
+            request_id,
+            files: Vec::new(),
+        }
+    }
+
+    /// Number of bytes of the file already received, 0 for unknown files
+    pub fn received(&self, file_name: &str) -> u64{
+        self.file(file_name).map(|file| file.received).unwrap_or(0)
+    }
+
+    /// Total number of bytes received over all files
+    pub fn total_received(&self) -> u64{
+        self.files.iter().map(|file| file.received).sum()
+    }
+
+    fn file(&self, file_name: &str) -> Option<&TransferredFile>{
+        self.files.iter().find(|file| file.name == file_name)
+    }
+}
+
+/// Rendering result received by [receive_result_streamed], the files are already written to disk
+#[derive(Debug, Clone)]
+pub struct StreamedResult{
+    /// Paths of the written files, in the order they were received
+    pub files: Vec<PathBuf>,
+    /// See [RenderingResult::durations]
+    pub durations: HashMap<String, Duration>,
+    /// See [RenderingResult::total_duration]
+    pub total_duration: Duration,
+    /// See [RenderingResult::page_counts]
+    pub page_counts: HashMap<String, u32>,
+}
+
+/// Sends a rendering result as a series of [Message::ResultChunk] instead of one large message
+/// Each file is split into chunks of at most [RESULT_CHUNK_SIZE] bytes, so only one chunk has to be encoded at a time.
+/// The stream is terminated by a [RenderingStatus::Finished] carrying the durations, but no files.
+/// Returns the number of bytes written to the socket.
+pub async fn send_result_streamed<S: AsyncWrite + Unpin>(socket: &mut S, request_id: uuid::Uuid, result: &RenderingResult, config: &TransportConfig) -> Result<usize, TransportError>{
+    send_result_streamed_resumed(socket, request_id, result, &ResultTransferState::new(request_id), config).await
+}
+
+/// Continues an interrupted [send_result_streamed] after the receiver sent its [ResultTransferState] in a [Message::ResumeResultTransfer]
+/// Completely received files are skipped, partially received ones are continued at the offset the receiver already has.
+/// Fails with [CommunicationError::InvalidResultChunk] if the receiver claims to have more bytes of a file than it has.
+pub async fn send_result_streamed_resumed<S: AsyncWrite + Unpin>(socket: &mut S, request_id: uuid::Uuid, result: &RenderingResult, state: &ResultTransferState, config: &TransportConfig) -> Result<usize, TransportError>{
+    let mut written = 0;
+
+    for file in result.files.iter(){
+        if state.file(&file.name).is_some_and(|file| file.complete){
+            continue
+        }
+
+        let start = state.received(&file.name);
+        let remaining = usize::try_from(start).ok().and_then(|start| file.content.get(start..))
+            .ok_or(TransportError::Communication(CommunicationError::InvalidResultChunk))?;
+        let mut offset = start;
+        let mut chunks = remaining.chunks(RESULT_CHUNK_SIZE).peekable();
+
+        // Empty files (or fully received ones without the last chunk) still need one chunk,
+        // otherwise they wouldn't be created / completed on the receiving side
+        if chunks.peek().is_none(){
+            written += send_chunk(socket, request_id, &file.name, start, Vec::new(), true, config).await?;
+        }
+
+        while let Some(data) = chunks.next(){
+            let last = chunks.peek().is_none();
+            written += send_chunk(socket, request_id, &file.name, offset, data.to_vec(), last, config).await?;
+            offset += data.len() as u64;
+        }
+    }
+
+    let finished = RenderingResult{
+        files: Vec::new(),
+        durations: result.durations.clone(),
+        total_duration: result.total_duration,
+        page_counts: result.page_counts.clone(),
+    };
+    let status = RenderingStatusUpdate{
+        request_id,
+        status: RenderingStatus::Finished(finished),
+    };
+    written += send_message_with_config(socket, Message::RenderingRequestStatus(status), config).await?;
+
+    Ok(written)
+}
+
+async fn send_chunk<S: AsyncWrite + Unpin>(socket: &mut S, request_id: uuid::Uuid, file_name: &str, offset: u64, data: Vec<u8>, last: bool, config: &TransportConfig) -> Result<usize, TransportError>{
+    let chunk = ResultChunk{
+        request_id,
+        file_name: file_name.to_string(),
+        offset,
+        data,
+        last,
+    };
+
+    send_message_with_config(socket, Message::ResultChunk(chunk), config).await
+}
+
+/// Receives a rendering result sent by [send_result_streamed] and writes the files directly into `dest`
+/// If `dest` does not exist, creates it. Files of the terminating [RenderingStatus::Finished] are written as well,
+/// so results sent as a single message by older servers are received the same way.
+/// Files sent as [RenderingStatus::PartialResult] are written as soon as they arrive, the stream still ends with [RenderingStatus::Finished].
+/// [Message::RenderingLog] messages in between are ignored.
+/// Chunks have to arrive in order, file names must not contain path separators and their request_id has to be `request_id`,
+/// otherwise receiving fails with [CommunicationError::InvalidResultChunk].
+pub async fn receive_result_streamed<S: AsyncRead + Unpin>(socket: &mut S, request_id: uuid::Uuid, dest: &Path, config: &TransportConfig) -> Result<StreamedResult, TransportError>{
+    let mut state = ResultTransferState::new(request_id);
+    receive_result_streamed_resumable(socket, dest, &mut state, config).await
+}
+
+/// Same as [receive_result_streamed], but records the progress in `state`
+///
+/// If the transfer fails (e.g. the connection dropped), `state` contains the bytes already written to `dest`.
+/// After reconnecting, send it as [Message::ResumeResultTransfer] and call this function again with the same `state` and `dest`:
+/// partially received files are continued at their offset instead of being received from the beginning.
+/// Bytes written after the last recorded chunk are discarded, so the files are byte-identical to the sent ones.
+/// Chunks and status updates of another request than [ResultTransferState::request_id] fail with [CommunicationError::InvalidResultChunk]
+/// (status updates only if the peer speaks protocol version 12 or newer, older ones don't send their request_id).
+pub async fn receive_result_streamed_resumable<S: AsyncRead + Unpin>(socket: &mut S, dest: &Path, state: &mut ResultTransferState, config: &TransportConfig) -> Result<StreamedResult, TransportError>{
+    tokio::fs::create_dir_all(dest).await?;
+
+    // Currently written file with its index in the state
+    let mut current: Option<(usize, File)> = None;
+
+    loop {
+        let (msg, _) = read_message_with_config(socket, config).await?;
+
+        // Statuses of peers older than protocol version 12 don't carry the request_id
+        let carries_request_id = match msg {
+            Message::ResultChunk(_) => true,
+            Message::RenderingRequestStatus(_) => config.protocol_version >= 12,
+            _ => false,
+        };
+        if carries_request_id && msg.request_id() != Some(state.request_id) {
+            tracing::warn!(message_type = msg.name(), request_id = ?msg.request_id(), expected_request_id = %state.request_id, "Received result of another request");
+            return Err(TransportError::Communication(CommunicationError::InvalidResultChunk))
+        }
+
+        match msg {
+            Message::ResultChunk(chunk) => {
+                let (index, mut file) = match current.take(){
+                    Some(current) => current,
+                    None => open_result_file(dest, state, &chunk.file_name).await?,
+                };
+
+                let transferred = &mut state.files[index];
+                if transferred.name != chunk.file_name || transferred.received != chunk.offset {
+                    return Err(TransportError::Communication(CommunicationError::InvalidResultChunk))
+                }
+                file.write_all(&chunk.data).await?;
+                // Only record the chunk once it is on disk, otherwise a resumed transfer could skip bytes
+                file.flush().await?;
+                transferred.received += chunk.data.len() as u64;
+
+                if chunk.last {
+                    transferred.complete = true;
+                } else {
+                    current = Some((index, file));
+                }
+            },
+            Message::RenderingRequestStatus(RenderingStatusUpdate{status: RenderingStatus::Finished(result), ..}) if current.is_none() => {
+                let mut files = Vec::new();
+                for file in state.files.iter(){
+                    files.push(result_file_path(dest, &file.name)?);
+                }
+                for file in result.files{
+                    files.push(write_result_file(dest, file).await?);
+                }
+
+                return Ok(StreamedResult{
+                    files,
+                    durations: result.durations,
+                    total_duration: result.total_duration,
+                    page_counts: result.page_counts,
+                })
+            },
+            Message::RenderingRequestStatus(RenderingStatusUpdate{status: RenderingStatus::PartialResult(file), ..}) if current.is_none() => {
+                let received = file.content.len() as u64;
+                let name = file.name.clone();
+                write_result_file(dest, file).await?;
+                state.files.retain(|file| file.name != name);
+                state.files.push(TransferredFile{
+                    name,
+                    received,
+                    complete: true,
+                });
+            },
+            Message::RenderingLog(_) => continue,
+            _ => return Err(TransportError::Communication(CommunicationError::UnexpectedMessageType))
+        }
+    }
+}
+
+/// Opens the file a chunk belongs to, continuing a partially received file or creating a new one
+/// Returns the index of the file in `state`.
+async fn open_result_file(dest: &Path, state: &mut ResultTransferState, file_name: &str) -> Result<(usize, File), TransportError>{
+    let path = result_file_path(dest, file_name)?;
+
+    match state.files.iter().position(|file| file.name == file_name){
+        Some(index) => {
+            let transferred = &state.files[index];
+            if transferred.complete{
+                return Err(TransportError::Communication(CommunicationError::InvalidResultChunk))
+            }
+
+            let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(&path).await?;
+            file.set_len(transferred.received).await?;
+            file.seek(SeekFrom::End(0)).await?;
+            Ok((index, file))
+        },
+        None => {
+            let file = File::create(&path).await?;
+            state.files.push(TransferredFile{
+                name: file_name.to_string(),
+                received: 0,
+                complete: false,
+            });
+            Ok((state.files.len() - 1, file))
+        }
+    }
+}
+
+async fn write_result_file(dest: &Path, file: NamedFile) -> Result<PathBuf, TransportError>{
+    let path = result_file_path(dest, &file.name)?;
+    tokio::fs::write(&path, file.content).await?;
+
+    Ok(path)
+}
+
+/// Joins the file name of a received result file to `dest`, rejects names which would end up outside of it
+fn result_file_path(dest: &Path, file_name: &str) -> Result<PathBuf, TransportError>{
+    if !is_safe_name(file_name) {
+        return Err(TransportError::Communication(CommunicationError::InvalidResultChunk))
+    }
+
+    Ok(dest.join(file_name))
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn result(files: Vec<(&str, Vec<u8>)>) -> RenderingResult{
+        RenderingResult{
+            files: files.into_iter().map(|(name, content)| NamedFile{
+                name: name.to_string(),
+                content,
+                mode: None,
+                modified: None,
+            }).collect(),
+            durations: HashMap::new(),
+            total_duration: Duration::from_secs(1),
+            page_counts: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn chunk_of_another_request_is_rejected(){
+        let dest = TempDir::new();
+        let config = TransportConfig::default();
+        let mut wire = Vec::new();
+        send_result_streamed(&mut wire, uuid::Uuid::new_v4(), &result(vec![("book.pdf", b"%PDF".to_vec())]), &config).await.unwrap();
+
+        let received = receive_result_streamed(&mut wire.as_slice(), uuid::Uuid::new_v4(), &dest.0, &config).await;
+        assert!(matches!(received, Err(TransportError::Communication(CommunicationError::InvalidResultChunk))));
+        assert!(!dest.0.join("book.pdf").exists());
+    }
+
+    #[tokio::test]
+    async fn status_of_another_request_is_rejected(){
+        let dest = TempDir::new();
+        let config = TransportConfig::default();
+        let request_id = uuid::Uuid::new_v4();
+        let mut wire = Vec::new();
+        send_result_streamed(&mut wire, uuid::Uuid::new_v4(), &result(Vec::new()), &config).await.unwrap();
+
+        let mut state = ResultTransferState::new(request_id);
+        let received = receive_result_streamed_resumable(&mut wire.as_slice(), &dest.0, &mut state, &config).await;
+        assert!(matches!(received, Err(TransportError::Communication(CommunicationError::InvalidResultChunk))));
+    }
+
+    #[tokio::test]
+    async fn status_of_old_peer_without_request_id_is_accepted(){
+        let dest = TempDir::new();
+        let config = TransportConfig{
+            protocol_version: 11,
+            ..Default::default()
+        };
+        let mut wire = Vec::new();
+        send_result_streamed(&mut wire, uuid::Uuid::new_v4(), &result(Vec::new()), &config).await.unwrap();
+
+        let mut state = ResultTransferState::new(uuid::Uuid::new_v4());
+        let received = receive_result_streamed_resumable(&mut wire.as_slice(), &dest.0, &mut state, &config).await.unwrap();
+        assert!(received.files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn truncated_transfer_resumes_byte_identical(){
+        let dest = TempDir::new();
+        let config = TransportConfig::default();
+        let request_id = uuid::Uuid::new_v4();
+        let book : Vec<u8> = (0..2 * RESULT_CHUNK_SIZE + 1000).map(|i| (i * 31 % 251) as u8).collect();
+        let sent = result(vec![("book.pdf", book.clone()), ("book.epub", b"epub".to_vec())]);
+
+        // Cut the connection in the middle of the second chunk of book.pdf
+        let mut wire = Vec::new();
+        send_result_streamed(&mut wire, request_id, &sent, &config).await.unwrap();
+        wire.truncate(RESULT_CHUNK_SIZE + RESULT_CHUNK_SIZE / 2);
+
+        let mut state = ResultTransferState::new(request_id);
+        let received = receive_result_streamed_resumable(&mut wire.as_slice(), &dest.0, &mut state, &config).await;
+        assert!(matches!(received, Err(TransportError::Io(_))), "{:?}", received.err());
+        assert_eq!(state.files, vec![TransferredFile{
+            name: "book.pdf".to_string(),
+            received: RESULT_CHUNK_SIZE as u64,
+            complete: false,
+        }]);
+        // Bytes written after the last recorded chunk have to be discarded when resuming
+        let mut file = OpenOptions::new().append(true).open(dest.0.join("book.pdf")).await.unwrap();
+        file.write_all(b"garbage").await.unwrap();
+
+        let mut wire = Vec::new();
+        send_result_streamed_resumed(&mut wire, request_id, &sent, &state, &config).await.unwrap();
+        assert!(wire.len() < book.len());
+
+        let received = receive_result_streamed_resumable(&mut wire.as_slice(), &dest.0, &mut state, &config).await.unwrap();
+        assert_eq!(received.files, vec![dest.0.join("book.pdf"), dest.0.join("book.epub")]);
+        assert_eq!(tokio::fs::read(dest.0.join("book.pdf")).await.unwrap(), book);
+        assert_eq!(tokio::fs::read(dest.0.join("book.epub")).await.unwrap(), b"epub");
+        assert!(state.files.iter().all(|file| file.complete));
+    }
+}